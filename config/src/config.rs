@@ -2,6 +2,7 @@
 
 use crate::deserialize_size;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Config object.
 #[non_exhaustive]
@@ -72,6 +73,8 @@ pub struct HeapPool {
 #[serde(rename_all = "kebab-case")]
 pub struct Probe {
     pub gdb_client_command: String,
+    #[serde(default)]
+    pub semihosting: bool,
     pub bmp: Option<ProbeBmp>,
     pub jlink: Option<ProbeJlink>,
     pub openocd: Option<ProbeOpenocd>,
@@ -111,6 +114,9 @@ pub struct ProbeOpenocd {
 pub struct Log {
     pub swo: Option<LogSwo>,
     pub dso: Option<LogDso>,
+    pub rtt: Option<LogRtt>,
+    #[serde(default)]
+    pub ports: BTreeMap<String, u32>,
 }
 
 #[non_exhaustive]
@@ -120,6 +126,11 @@ pub struct LogSwo {
     pub reset_freq: u32,
     pub baud_rate: u32,
     pub serial_endpoint: Option<String>,
+    #[serde(default)]
+    pub parity: SerialParity,
+    #[serde(default)]
+    pub flow_control: SerialFlowControl,
+    pub core_clock: Option<u32>,
 }
 
 #[non_exhaustive]
@@ -128,4 +139,45 @@ pub struct LogSwo {
 pub struct LogDso {
     pub baud_rate: u32,
     pub serial_endpoint: String,
+    #[serde(default)]
+    pub parity: SerialParity,
+    #[serde(default)]
+    pub flow_control: SerialFlowControl,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LogRtt {
+    pub control_block: Option<u32>,
+    pub server_port: Option<u16>,
+    pub serial_endpoint: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialParity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialFlowControl {
+    None,
+    Hardware,
+    Software,
+}
+
+impl Default for SerialParity {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl Default for SerialFlowControl {
+    fn default() -> Self {
+        Self::None
+    }
 }
@@ -5,14 +5,17 @@
 use crate::{
     color::Color,
     probe::{Log, Probe},
-    utils::de_from_str,
+    utils::{de_from_str, parse_duration},
 };
-use anyhow::Error;
 use drone_config::parse_size;
+use regex::Regex;
+use serde::Deserialize;
 use std::{
     ffi::{OsStr, OsString},
+    num::ParseIntError,
     os::unix::ffi::OsStrExt,
     path::PathBuf,
+    time::Duration,
 };
 use structopt::StructOpt;
 
@@ -40,13 +43,17 @@ pub enum Cmd {
     /// Analyze or modify the heap layout
     Heap(HeapCmd),
     /// Capture the log output
-    Log(LogCmd),
+    Log(Box<LogCmd>),
     /// Create a new Drone project
     New(NewCmd),
+    /// Profile the firmware by sampling the program counter over SWO
+    Profile(ProfileCmd),
     /// Assert the reset signal
     Reset(ResetCmd),
     /// Print a list of supported target devices, debug probes, and log types
     Support,
+    /// Run the tests on the target device
+    Test(TestCmd),
 }
 
 #[derive(Debug, StructOpt)]
@@ -104,6 +111,23 @@ pub struct HeapCmd {
     /// Maximum size of the heap
     #[structopt(short, long, parse(try_from_str = parse_size))]
     pub size: Option<u32>,
+    /// Firmware to resolve the allocation callers recorded in the trace
+    #[structopt(long, parse(from_os_str))]
+    pub firmware: Option<PathBuf>,
+    /// Capture the heap trace from the device, refreshing the statistics as
+    /// the firmware runs, and save it to the trace file
+    #[structopt(long)]
+    pub live: bool,
+    /// Reset before the live capture
+    #[structopt(short, long, requires = "live")]
+    pub reset: bool,
+    /// Heap trace port for the live capture (defaults to `heaptrace` from
+    /// `[log.ports]`, or 31)
+    #[structopt(long, requires = "live")]
+    pub port: Option<u32>,
+    /// Pool load percentage to flag during the live capture (defaults to 90)
+    #[structopt(long, requires = "live")]
+    pub threshold: Option<u32>,
     #[structopt(subcommand)]
     pub heap_sub_cmd: Option<HeapSubCmd>,
 }
@@ -112,6 +136,13 @@ pub struct HeapCmd {
 pub enum HeapSubCmd {
     /// Generate an optimized heap map from the given trace file
     Generate(HeapGenerateCmd),
+    /// Report allocations outstanding at the end of the trace
+    Leaks(HeapLeaksCmd),
+    /// Export live block counts over the trace events
+    Timeline(HeapTimelineCmd),
+    /// Replay the trace against the pools configured in Drone.toml (exits
+    /// with a non-zero status if an allocation fails)
+    Simulate(HeapSimulateCmd),
 }
 
 #[derive(Debug, StructOpt)]
@@ -119,8 +150,42 @@ pub struct HeapGenerateCmd {
     /// Number of pools
     #[structopt(short, long, parse(try_from_str = parse_size))]
     pub pools: u32,
+    /// Optimization objective: fragmentation (minimal fragmentation for the
+    /// heap size), size (minimal heap size fitting the trace)
+    #[structopt(long, default_value = "fragmentation", parse(try_from_str = de_from_str))]
+    pub objective: HeapObjective,
+    /// Safety margin in percent of the trace load for the `size` objective
+    #[structopt(long, default_value = "10")]
+    pub margin: u32,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapLeaksCmd {
+    /// Flag block sizes whose live count keeps growing over the trace, past
+    /// the first window
+    #[structopt(long)]
+    pub steady_state: bool,
+    /// Number of windows the trace is split into for the steady-state check
+    #[structopt(long, default_value = "8")]
+    pub windows: usize,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapTimelineCmd {
+    /// Output format: csv, json
+    #[structopt(long, default_value = "csv", parse(try_from_str = de_from_str))]
+    pub format: HeapTimelineFormat,
+    /// Output file (defaults to the standard output)
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+    /// Write a chart of the live block counts to an SVG file
+    #[structopt(long, parse(from_os_str))]
+    pub svg: Option<PathBuf>,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct HeapSimulateCmd {}
+
 #[derive(Debug, StructOpt)]
 pub struct ResetCmd {}
 
@@ -152,6 +217,112 @@ pub struct LogCmd {
     /// Reset before the operation
     #[structopt(short, long)]
     pub reset: bool,
+    /// Prefix each line with the target timestamp (SWO only)
+    #[structopt(short, long)]
+    pub timestamps: bool,
+    /// Prefix each standard output line with the port label
+    #[structopt(short, long)]
+    pub labels: bool,
+    /// Output format of the standard output and sockets: text, jsonl (one JSON
+    /// object per line). Files always receive the raw port data
+    #[structopt(long, default_value = "text", parse(try_from_str = de_from_str))]
+    pub format: LogFormat,
+    /// Write exception timeline to the file in Chrome Trace Event format (SWO
+    /// only)
+    #[structopt(short, long, parse(from_os_str))]
+    pub exceptions: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub files: LogFileOpts,
+    /// Firmware ELF file with interned format strings and RTT control block
+    #[structopt(long, parse(from_os_str))]
+    pub firmware: Option<PathBuf>,
+    /// Decode deferred-format frames on the port using the strings from
+    /// `--firmware`
+    #[structopt(long, requires = "firmware")]
+    pub interned: Option<u8>,
+    /// Route the semihosting console output to the port
+    #[structopt(long, default_value = "0")]
+    pub semihosting_port: u8,
+    /// Forward standard input to the target port (dsoserial only)
+    #[structopt(long, conflicts_with = "replay")]
+    pub stdin_port: Option<u8>,
+    /// Forward the file instead of standard input to `--stdin-port`
+    #[structopt(long, parse(from_os_str), requires = "stdin-port")]
+    pub stdin_file: Option<PathBuf>,
+    /// Stop after a line matching the pattern is logged, exit with code 5 if
+    /// the log ends without a match
+    #[structopt(long)]
+    pub until: Option<Regex>,
+    /// Stop and exit with code 2 after a line matching the pattern is logged
+    #[structopt(long)]
+    pub fail_on: Option<Regex>,
+    /// Stop and exit with code 3 after the duration (e.g. 500ms, 30s, 5m)
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub timeout: Option<Duration>,
+    /// Stop and exit with code 4 after receiving the number of bytes (e.g.
+    /// 64K)
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub max_bytes: Option<u32>,
+    /// Record the raw byte stream from the probe to the file
+    #[structopt(long, parse(from_os_str), conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Decode a previously recorded raw byte stream instead of capturing
+    #[structopt(long, parse(from_os_str))]
+    pub replay: Option<PathBuf>,
+    /// Protocol of the replayed stream: swo, dso (defaults to the configured
+    /// log type)
+    #[structopt(long, requires = "replay", parse(try_from_str = de_from_str))]
+    pub protocol: Option<LogProtocol>,
+    /// Log output (format: \[path\]\[:port\]..., tcp://host:port\[:port\]...,
+    /// or unix://path\[:port\]...), where port is a number or a name from
+    /// `[log.ports]`
+    #[structopt(
+        name = "OUTPUT",
+        parse(try_from_os_str = parse_log_output)
+    )]
+    pub outputs: Vec<LogOutput>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TestCmd {
+    /// Path to the compiled test binary (built with `cargo test` if omitted)
+    #[structopt(parse(from_os_str))]
+    pub firmware: Option<PathBuf>,
+    /// Target triple to build the tests for (defaults to `CARGO_BUILD_TARGET`)
+    #[structopt(long)]
+    pub target: Option<String>,
+    /// Space-separated list of features to build the tests with
+    #[structopt(long)]
+    pub features: Option<String>,
+    /// Port carrying the test results
+    #[structopt(long, default_value = "30")]
+    pub port: u8,
+    /// Ports to print to the standard output
+    #[structopt(long, use_delimiter = true, default_value = "0,1")]
+    pub console: Vec<u32>,
+    /// Fail the test run if it doesn't finish within the duration (e.g. 30s,
+    /// 5m)
+    #[structopt(long, default_value = "60s", parse(try_from_str = parse_duration))]
+    pub timeout: Duration,
+    /// Test harness arguments passed by cargo when used as a runner (ignored)
+    #[structopt(parse(from_os_str))]
+    pub args: Vec<OsString>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ProfileCmd {
+    /// Path to the compiled firmware file
+    #[structopt(parse(from_os_str))]
+    pub firmware: PathBuf,
+    /// Reset before the operation
+    #[structopt(short, long)]
+    pub reset: bool,
+    /// Write folded stacks for flamegraph tools to the file
+    #[structopt(short, long, parse(from_os_str))]
+    pub folded: Option<PathBuf>,
+    /// Write the flat profile (`function count` lines) to the file
+    #[structopt(long, parse(from_os_str))]
+    pub flat: Option<PathBuf>,
     /// Log output (format: \[path\]\[:port\]...)
     #[structopt(
         name = "OUTPUT",
@@ -160,21 +331,224 @@ pub struct LogCmd {
     pub outputs: Vec<LogOutput>,
 }
 
+/// Log output file options.
+#[derive(Debug, Clone, StructOpt)]
+pub struct LogFileOpts {
+    /// Output file mode: write (existing file from the start), create (new
+    /// file only), truncate, append
+    #[structopt(long, default_value = "write", parse(try_from_str = de_from_str))]
+    pub mode: LogFileMode,
+    /// Rotate output files exceeding the size in bytes (e.g. 64M)
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub rotate_size: Option<u32>,
+    /// Rotate output files every number of seconds
+    #[structopt(long)]
+    pub rotate_interval: Option<u64>,
+    /// Number of rotated output files to keep
+    #[structopt(long, default_value = "5")]
+    pub rotate_keep: u32,
+}
+
+/// Log output file mode.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFileMode {
+    /// Write to an existing file from the start.
+    Write,
+    /// Create a new file, failing if it exists.
+    Create,
+    /// Create a file or truncate an existing one.
+    Truncate,
+    /// Create a file or append to an existing one.
+    Append,
+}
+
+/// Log output format.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Raw text as written by the target.
+    Text,
+    /// JSON object per line with the port, the text or bytes, and the
+    /// timestamps. Applies to the standard output and sockets only.
+    Jsonl,
+}
+
+/// Heap layout optimization objective.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeapObjective {
+    /// Minimal fragmentation for the heap size.
+    Fragmentation,
+    /// Minimal heap size fitting the trace with a safety margin.
+    Size,
+}
+
+/// Heap timeline export format.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeapTimelineFormat {
+    /// Comma-separated values, one row per event.
+    Csv,
+    /// JSON object with the counts after each event.
+    Json,
+}
+
+impl Default for LogFileOpts {
+    fn default() -> Self {
+        Self { mode: LogFileMode::Write, rotate_size: None, rotate_interval: None, rotate_keep: 5 }
+    }
+}
+
+impl Default for LogCmd {
+    fn default() -> Self {
+        Self {
+            reset: false,
+            timestamps: false,
+            labels: false,
+            format: LogFormat::default(),
+            exceptions: None,
+            files: LogFileOpts::default(),
+            firmware: None,
+            interned: None,
+            semihosting_port: 0,
+            stdin_port: None,
+            stdin_file: None,
+            until: None,
+            fail_on: None,
+            timeout: None,
+            max_bytes: None,
+            record: None,
+            replay: None,
+            protocol: None,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self::Text
+    }
+}
+
+/// Log byte stream protocol.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogProtocol {
+    /// ARM® SWO.
+    Swo,
+    /// Drone Serial Output.
+    Dso,
+}
+
 /// Log output.
 #[derive(Debug, Clone)]
 pub struct LogOutput {
     /// Selected ports.
     pub ports: Vec<u32>,
-    /// Output path.
-    pub path: OsString,
+    /// Selected port names, to be resolved with the `[log.ports]` table.
+    pub names: Vec<String>,
+    /// Output sink.
+    pub sink: LogSink,
+}
+
+/// Log output sink.
+#[derive(Debug, Clone)]
+pub enum LogSink {
+    /// Standard output.
+    Stdout,
+    /// File at the path.
+    File(PathBuf),
+    /// TCP socket listening at the address.
+    Tcp(String),
+    /// Unix domain socket listening at the path.
+    Unix(PathBuf),
+    /// No output, only selects the ports.
+    Discard,
 }
 
+const TCP_SCHEME: &[u8] = b"tcp://";
+const UNIX_SCHEME: &[u8] = b"unix://";
+
 fn parse_log_output(src: &OsStr) -> Result<LogOutput, OsString> {
-    let mut chunks = src.as_bytes().split(|&b| b == b':');
-    let path = OsStr::from_bytes(chunks.next().unwrap()).into();
-    let ports = chunks
-        .map(|port| Ok(String::from_utf8(port.to_vec())?.parse()?))
-        .collect::<Result<_, Error>>()
-        .map_err(|err| err.to_string())?;
-    Ok(LogOutput { ports, path })
+    let src = src.as_bytes();
+    let (sink, ports) = if src.starts_with(TCP_SCHEME) {
+        let src = &src[TCP_SCHEME.len()..];
+        // The colons of a bracketed IPv6 host are not separators.
+        let host_end = if src.starts_with(b"[") {
+            src.iter().position(|&b| b == b']').ok_or("unclosed `[` in the TCP address")?
+        } else {
+            0
+        };
+        let end = src[host_end..]
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b':')
+            .nth(1)
+            .map_or(src.len(), |(i, _)| host_end + i);
+        let address = String::from_utf8(src[..end].to_vec()).map_err(|err| err.to_string())?;
+        (LogSink::Tcp(address), &src[end..])
+    } else if src.starts_with(UNIX_SCHEME) {
+        let src = &src[UNIX_SCHEME.len()..];
+        let end = src.iter().position(|&b| b == b':').unwrap_or_else(|| src.len());
+        (LogSink::Unix(OsStr::from_bytes(&src[..end]).into()), &src[end..])
+    } else {
+        let end = src.iter().position(|&b| b == b':').unwrap_or_else(|| src.len());
+        let path = &src[..end];
+        let sink = if path.is_empty() {
+            LogSink::Stdout
+        } else {
+            LogSink::File(OsStr::from_bytes(path).into())
+        };
+        (sink, &src[end..])
+    };
+    let mut output = LogOutput { ports: Vec::new(), names: Vec::new(), sink };
+    for port in ports.split(|&b| b == b':').skip(1) {
+        let port = String::from_utf8(port.to_vec()).map_err(|err| err.to_string())?;
+        if port.is_empty() {
+            return Err("empty port".into());
+        } else if port.starts_with(|c: char| c.is_ascii_digit()) {
+            output.ports.push(port.parse().map_err(|err: ParseIntError| err.to_string())?);
+        } else {
+            output.names.push(port);
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn parse(src: &str) -> Result<LogOutput, OsString> {
+        parse_log_output(OsStr::new(src))
+    }
+
+    #[test]
+    fn tcp_ipv6_output() {
+        let output = parse("tcp://[::1]:4000:0:1").unwrap();
+        assert!(matches!(&output.sink, LogSink::Tcp(address) if address == "[::1]:4000"));
+        assert_eq!(output.ports, [0, 1]);
+    }
+
+    #[test]
+    fn tcp_ipv4_output() {
+        let output = parse("tcp://127.0.0.1:4000").unwrap();
+        assert!(matches!(&output.sink, LogSink::Tcp(address) if address == "127.0.0.1:4000"));
+        assert!(output.ports.is_empty());
+    }
+
+    #[test]
+    fn unix_output() {
+        let output = parse("unix:///run/x.sock:31").unwrap();
+        assert!(matches!(&output.sink, LogSink::Unix(path) if path == Path::new("/run/x.sock")));
+        assert_eq!(output.ports, [31]);
+    }
+
+    #[test]
+    fn unclosed_ipv6_host() {
+        assert_eq!(parse("tcp://[::1").unwrap_err(), "unclosed `[` in the TCP address");
+    }
 }
@@ -2,20 +2,15 @@
 
 use crate::cli::EnvCmd;
 use anyhow::{anyhow, bail, Result};
-use std::{env, os::unix::process::CommandExt, process::Command};
+use std::{env, ffi::OsStr, os::unix::process::CommandExt, process::Command};
 
 /// Runs `drone env` command.
 pub fn run(cmd: EnvCmd) -> Result<()> {
     let EnvCmd { target, command } = cmd;
     let mut iter = command.iter();
     if let Some(command) = iter.next() {
-        let mut command = Command::new(command);
         let target = target.as_ref().cloned().map_or_else(host_target, Ok)?;
-        command.env("CARGO_BUILD_TARGET", &target);
-        if let Some(value) = env::var_os("DRONE_RUSTFLAGS") {
-            let key = format!("CARGO_TARGET_{}_RUSTFLAGS", upcase_target(&target));
-            command.env(key, value);
-        }
+        let mut command = env_command(&target, command);
         command.args(iter);
         Err(anyhow!(command.exec()))
     } else {
@@ -23,6 +18,17 @@ pub fn run(cmd: EnvCmd) -> Result<()> {
     }
 }
 
+/// Creates a command with the cross-compile environment for `target`.
+pub fn env_command(target: &str, program: impl AsRef<OsStr>) -> Command {
+    let mut command = Command::new(program);
+    command.env("CARGO_BUILD_TARGET", target);
+    if let Some(value) = env::var_os("DRONE_RUSTFLAGS") {
+        let key = format!("CARGO_TARGET_{}_RUSTFLAGS", upcase_target(target));
+        command.env(key, value);
+    }
+    command
+}
+
 fn host_target() -> Result<String> {
     let mut rustc = Command::new("rustc");
     rustc.arg("--verbose");
@@ -1,6 +1,8 @@
 //! `drone flash` command.
 
-use crate::{cli::FlashCmd, probe, probe::Probe, templates::Registry, utils::register_signals};
+use crate::{
+    cli::FlashCmd, probe, probe::Probe, serial, templates::Registry, utils::register_signals,
+};
 use anyhow::Result;
 use drone_config as config;
 use std::convert::TryFrom;
@@ -9,7 +11,8 @@ use std::convert::TryFrom;
 pub fn run(cmd: FlashCmd) -> Result<()> {
     let signals = register_signals()?;
     let registry = Registry::new()?;
-    let config = config::Config::read_from_current_dir()?;
+    let mut config = config::Config::read_from_current_dir()?;
+    serial::resolve_endpoints(&mut config)?;
     let probe = Probe::try_from(&config)?;
     probe::flash(probe)(cmd, signals, registry, config)
 }
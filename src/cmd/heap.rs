@@ -1,66 +1,128 @@
 //! `drone heap` command.
 
 use crate::{
-    cli::{HeapCmd, HeapGenerateCmd, HeapSubCmd},
+    cli::{
+        HeapCmd, HeapGenerateCmd, HeapLeaksCmd, HeapObjective, HeapSimulateCmd, HeapSubCmd,
+        HeapTimelineCmd, HeapTimelineFormat, LogCmd, LogFileMode, LogFileOpts, LogOutput, LogSink,
+    },
     color::Color,
     heap,
-    heap::TraceMap,
+    heap::{layout::Objective, timeline::Timeline, trace::Parser, CallerMap, PoolLoad, TraceMap},
+    log, probe,
+    probe::{Log, Probe},
+    profile::Symbols,
+    serial,
+    templates::Registry,
+    utils::{register_signals, ExitCodeError},
 };
-use ansi_term::Color::{Cyan, Yellow};
-use anyhow::Result;
+use ansi_term::Color::{Cyan, Red, Yellow};
+use anyhow::{bail, Result};
 use drone_config::{self as config, format_size};
 use prettytable::{cell, format, row, Table};
 use std::{
+    convert::TryFrom,
     fs::File,
-    io::{stderr, stdout},
+    io,
+    io::{prelude::*, stderr, stdout},
+    path::Path,
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+/// Heap trace port used when it's not declared in `[log.ports]`.
+const HEAPTRACE_PORT: u32 = 31;
+
+/// Pool load percentage flagged during the live capture by default.
+const LOAD_THRESHOLD: u32 = 90;
+
+/// Minimum interval between live statistics refreshes.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Live heap statistics shared with the trace parsing thread.
+struct Live {
+    trace: TraceMap,
+    pools: Vec<PoolLoad>,
+    lines: usize,
+    done: bool,
+}
+
+/// Reader of data chunks received from a channel. The stream ends when the
+/// channel is disconnected.
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
 /// Runs `drone heap` command.
 pub fn run(cmd: HeapCmd, color: Color) -> Result<()> {
-    let HeapCmd { trace_file, size, heap_sub_cmd } = cmd;
+    let HeapCmd { trace_file, size, firmware, live, reset, port, threshold, heap_sub_cmd } = cmd;
     let size = size.map_or_else(
         || config::Config::read_from_current_dir().map(|config| config.heap.size),
         Ok,
     )?;
-    let mut trace = TraceMap::new();
-    if let Ok(file) = File::open(&trace_file) {
-        heap::read_trace(&mut trace, file, size)?;
-        if trace.is_empty() {
+    let trace = if live {
+        run_live(&trace_file, size, reset, port, threshold.unwrap_or(LOAD_THRESHOLD), color)?
+    } else {
+        let mut trace = TraceMap::new();
+        if let Ok(file) = File::open(&trace_file) {
+            heap::read_trace(&mut trace, file, size)?;
+            if trace.is_empty() {
+                eprintln!(
+                    "{}: file `{}` is empty.",
+                    color.bold_fg("warning", Yellow),
+                    trace_file.display()
+                );
+            } else {
+                print_table(&mut stderr(), &trace, size, color)?;
+                let mut callers = CallerMap::new();
+                heap::read_callers(&mut callers, File::open(&trace_file)?)?;
+                if !callers.is_empty() {
+                    let symbols = firmware.as_deref().map(Symbols::read).transpose()?;
+                    print_callers(&callers, symbols.as_ref(), color)?;
+                }
+            }
+        } else {
             eprintln!(
-                "{}: file `{}` is empty.",
+                "{}: file `{}` not exists.",
                 color.bold_fg("warning", Yellow),
                 trace_file.display()
             );
-        } else {
-            print_table(&trace, size, color)?;
         }
-    } else {
-        eprintln!(
-            "{}: file `{}` not exists.",
-            color.bold_fg("warning", Yellow),
-            trace_file.display()
-        );
-    }
+        trace
+    };
     match heap_sub_cmd {
         Some(HeapSubCmd::Generate(cmd)) => generate(cmd, &trace, size, color),
+        Some(HeapSubCmd::Leaks(cmd)) => leaks(cmd, &trace_file, size, firmware.as_deref(), color),
+        Some(HeapSubCmd::Timeline(cmd)) => timeline(cmd, &trace_file, size, color),
+        Some(HeapSubCmd::Simulate(cmd)) => simulate(cmd, &trace_file, firmware.as_deref(), color),
         None => Ok(()),
     }
 }
 
 /// Runs `drone heap generate` command.
 pub fn generate(cmd: HeapGenerateCmd, trace: &TraceMap, size: u32, color: Color) -> Result<()> {
-    let HeapGenerateCmd { pools } = cmd;
+    let HeapGenerateCmd { pools, objective, margin } = cmd;
     if trace.is_empty() {
         let layout = heap::layout::empty(size, pools);
         heap::layout::render(&mut stdout(), &layout)?;
     } else {
-        let (layout, frag) = heap::layout::optimize(&trace, size, pools)?;
+        let objective = match objective {
+            HeapObjective::Fragmentation => Objective::Fragmentation { size },
+            HeapObjective::Size => Objective::Size { margin },
+        };
+        let (layout, frag) = heap::layout::optimize(&trace, pools, objective)?;
+        let total = heap::layout::total(&layout);
         eprintln!();
         eprintln!("{}", color.bold_fg(&format!("{:=^80}", " OPTIMIZED LAYOUT "), Cyan));
         heap::layout::render(&mut stdout(), &layout)?;
         eprintln!(
             "# fragmentation: {}",
-            color.bold(&format!("{} / {:.2}%", frag, f64::from(frag) / f64::from(size) * 100.0))
+            color.bold(&format!("{} / {:.2}%", frag, f64::from(frag) / total as f64 * 100.0))
         );
         eprintln!(
             "# {}: replace the existing [heap] section in Drone.toml",
@@ -70,7 +132,320 @@ pub fn generate(cmd: HeapGenerateCmd, trace: &TraceMap, size: u32, color: Color)
     Ok(())
 }
 
-fn print_table(trace: &TraceMap, size: u32, color: Color) -> Result<()> {
+/// Runs `drone heap leaks` command.
+pub fn leaks(
+    cmd: HeapLeaksCmd,
+    trace_file: &Path,
+    size: u32,
+    firmware: Option<&Path>,
+    color: Color,
+) -> Result<()> {
+    let HeapLeaksCmd { steady_state, windows } = cmd;
+    let packets = heap::read_packets(File::open(trace_file)?)?;
+    let symbols = firmware.map(Symbols::read).transpose()?;
+    let leaks = heap::leaks::outstanding(&packets, size)?;
+    eprintln!();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " OUTSTANDING ALLOCATIONS "), Cyan));
+    if leaks.is_empty() {
+        eprintln!("No outstanding allocations.");
+    } else {
+        let mut table = Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+        table.set_titles(row![
+            r->color.bold("Block Size"),
+            r->color.bold("Blocks"),
+            r->color.bold("Bytes Held"),
+            color.bold("Caller"),
+        ]);
+        for leak in &leaks {
+            table.add_row(row![
+                r->format_size(leak.size),
+                r->leak.count,
+                r->leak.bytes(),
+                leak.caller.map_or_else(|| "-".to_string(), |pc| caller_name(pc, symbols.as_ref())),
+            ]);
+        }
+        table.print(&mut stderr())?;
+        eprintln!();
+        eprintln!(
+            "Bytes held: {}",
+            color.bold(&leaks.iter().map(heap::leaks::Leak::bytes).sum::<u64>().to_string())
+        );
+    }
+    if steady_state {
+        let growing = heap::leaks::growth(&packets, size, windows)?
+            .into_iter()
+            .filter(heap::leaks::Growth::is_growing)
+            .collect::<Vec<_>>();
+        eprintln!();
+        eprintln!("{}", color.bold_fg(&format!("{:=^80}", " STEADY STATE "), Cyan));
+        if growing.is_empty() {
+            eprintln!("No block sizes keep growing over the trace.");
+        } else {
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+            table.set_titles(row![
+                r->color.bold("Block Size"),
+                color.bold("Minimum Live Blocks per Window"),
+            ]);
+            for growth in &growing {
+                let minima =
+                    growth.minima.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ");
+                table.add_row(row![r->format_size(growth.size), minima]);
+            }
+            table.print(&mut stderr())?;
+            eprintln!();
+            eprintln!(
+                "{}: {} block sizes keep growing over the trace.",
+                color.bold_fg("warning", Yellow),
+                growing.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Runs `drone heap timeline` command.
+pub fn timeline(cmd: HeapTimelineCmd, trace_file: &Path, size: u32, color: Color) -> Result<()> {
+    let HeapTimelineCmd { format, output, svg } = cmd;
+    let packets = heap::read_packets(File::open(trace_file)?)?;
+    let pools =
+        config::Config::read_from_current_dir().map(|config| config.heap.pools).unwrap_or_default();
+    let timeline = Timeline::build(&packets, size, &pools)?;
+    let mut w: Box<dyn Write> = match &output {
+        Some(output) => Box::new(File::create(output)?),
+        None => Box::new(stdout()),
+    };
+    match format {
+        HeapTimelineFormat::Csv => timeline.write_csv(&mut w)?,
+        HeapTimelineFormat::Json => timeline.write_json(&mut w)?,
+    }
+    if let Some((event, bytes)) = timeline.peak() {
+        eprintln!();
+        eprintln!("Peak heap load: {} at event {}", color.bold(&format!("{} bytes", bytes)), event);
+    }
+    if let Some(output) = output {
+        eprintln!("# {}: timeline written to `{}`", color.bold_fg("hint", Cyan), output.display());
+    }
+    if let Some(svg) = svg {
+        timeline.write_svg(&mut File::create(&svg)?)?;
+        eprintln!("# {}: chart written to `{}`", color.bold_fg("hint", Cyan), svg.display());
+    }
+    Ok(())
+}
+
+/// Runs `drone heap simulate` command.
+pub fn simulate(
+    cmd: HeapSimulateCmd,
+    trace_file: &Path,
+    firmware: Option<&Path>,
+    color: Color,
+) -> Result<()> {
+    let HeapSimulateCmd {} = cmd;
+    let config = config::Config::read_from_current_dir()?;
+    let packets = heap::read_packets(File::open(trace_file)?)?;
+    let symbols = firmware.map(Symbols::read).transpose()?;
+    let simulation = heap::simulate::simulate(&packets, &config.heap.pools);
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        r->color.bold("Pool Block"),
+        r->color.bold("Capacity"),
+        r->color.bold("Peak Load"),
+        r->color.bold("Peak Usage"),
+        r->color.bold("Spilled In"),
+    ]);
+    for pool in &simulation.pools {
+        table.add_row(row![
+            r->format_size(pool.block),
+            r->pool.capacity,
+            r->pool.peak,
+            r->format!("{:.2}%", f64::from(pool.peak) / f64::from(pool.capacity.max(1)) * 100.0),
+            r->pool.spilled_in,
+        ]);
+    }
+    eprintln!();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " SIMULATION "), Cyan));
+    table.print(&mut stderr())?;
+    eprintln!();
+    eprintln!(
+        "Spilled allocations: {}",
+        color.bold(&format!(
+            "{} / {} / {:.2}%",
+            simulation.spills,
+            simulation.allocations,
+            f64::from(simulation.spills) / f64::from(simulation.allocations.max(1)) * 100.0
+        ))
+    );
+    if let Some(failure) = &simulation.first_failure {
+        let caller = failure.site.map_or_else(String::new, |site| {
+            format!(" from {}", caller_name(site.caller, symbols.as_ref()))
+        });
+        eprintln!(
+            "{}: {} allocations would fail, the first at event {}: {} bytes{}",
+            color.bold_fg("error", Red),
+            simulation.failures,
+            failure.event,
+            failure.size,
+            caller
+        );
+        bail!(ExitCodeError(1));
+    }
+    eprintln!("All allocations fit in the configured pools.");
+    Ok(())
+}
+
+/// Captures the heap trace from the device, saving it to `trace_file` and
+/// refreshing the statistics as the firmware runs.
+fn run_live(
+    trace_file: &Path,
+    size: u32,
+    reset: bool,
+    port: Option<u32>,
+    threshold: u32,
+    color: Color,
+) -> Result<TraceMap> {
+    let signals = register_signals()?;
+    let registry = Registry::new()?;
+    let mut config = config::Config::read_from_current_dir()?;
+    serial::resolve_endpoints(&mut config)?;
+    let probe = Probe::try_from(&config)?;
+    let log = Log::try_from(&config)?;
+    let log_fn = probe::log_fn(probe, log)?;
+    let port = port
+        .or_else(|| config.log.as_ref()?.ports.get("heaptrace").copied())
+        .unwrap_or(HEAPTRACE_PORT);
+    if port as usize >= log::PORTS_COUNT {
+        bail!("Port {} is out of range", port);
+    }
+    let outputs = vec![LogOutput {
+        ports: vec![port],
+        names: Vec::new(),
+        sink: LogSink::File(trace_file.to_path_buf()),
+    }];
+    probe::check_rtt_channels(probe, log, &outputs)?;
+
+    let mut pools = heap::pool_loads(&config.heap.pools);
+    let live = Arc::new(Mutex::new(Live {
+        trace: TraceMap::new(),
+        pools: pools.clone(),
+        lines: 0,
+        done: false,
+    }));
+    let (data, receiver) = channel();
+    thread::spawn({
+        let live = Arc::clone(&live);
+        move || follow(receiver, &live, size, threshold, color)
+    });
+    #[allow(clippy::cast_possible_truncation)]
+    let options = log::Options {
+        files: LogFileOpts { mode: LogFileMode::Truncate, ..LogFileOpts::default() },
+        port_data: Some((port as u8, data)),
+        ..log::Options::default()
+    };
+    let cmd = LogCmd { reset, outputs, ..LogCmd::default() };
+    log_fn(cmd, options, signals, registry, config, color)?;
+
+    let lines = {
+        let mut live = live.lock().unwrap();
+        live.done = true;
+        live.lines
+    };
+    erase(&mut stderr(), lines)?;
+    // The parsing thread may lag behind the capture, so the final statistics
+    // are built from the saved trace file.
+    let mut trace = TraceMap::new();
+    if let Err(err) = read_saved(trace_file, &mut trace, &mut pools, size) {
+        eprintln!("{}: heap trace stream: {}", color.bold_fg("warning", Yellow), err);
+    }
+    if trace.is_empty() {
+        eprintln!("{}: no heap trace received.", color.bold_fg("warning", Yellow));
+        return Ok(trace);
+    }
+    print_table(&mut stderr(), &trace, size, color)?;
+    print_pools(&mut stderr(), &pools, threshold, color)?;
+    for pool in &pools {
+        if u64::from(pool.max) * 100 >= u64::from(pool.capacity) * u64::from(threshold) {
+            eprintln!(
+                "{}: pool of {} blocks reached {} / {} blocks.",
+                color.bold_fg("warning", Yellow),
+                format_size(pool.block),
+                pool.max,
+                pool.capacity
+            );
+        }
+    }
+    Ok(trace)
+}
+
+/// Applies all packets of the saved `trace_file`. The statistics gathered
+/// before an error are kept.
+fn read_saved(
+    trace_file: &Path,
+    trace: &mut TraceMap,
+    pools: &mut [PoolLoad],
+    size: u32,
+) -> Result<()> {
+    for packet in Parser::new(File::open(trace_file)?)? {
+        heap::apply(trace, pools, packet?, size)?;
+    }
+    Ok(())
+}
+
+/// Parses the heap trace stream, refreshing the statistics on the standard
+/// error. The stream is abandoned on the first error.
+fn follow(
+    receiver: Receiver<Vec<u8>>,
+    live: &Mutex<Live>,
+    size: u32,
+    threshold: u32,
+    color: Color,
+) {
+    let tty = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+    let reader = ChannelReader { receiver, chunk: Vec::new(), position: 0 };
+    let mut refreshed = Instant::now();
+    for packet in Parser::new(reader).into_iter().flatten() {
+        let mut live = live.lock().unwrap();
+        let live = &mut *live;
+        if live.done {
+            break;
+        }
+        if packet
+            .map_err(Into::into)
+            .and_then(|packet| heap::apply(&mut live.trace, &mut live.pools, packet, size))
+            .is_err()
+        {
+            break;
+        }
+        if refreshed.elapsed() < REFRESH_INTERVAL {
+            continue;
+        }
+        refreshed = Instant::now();
+        let mut buf = Vec::new();
+        if print_table(&mut buf, &live.trace, size, color)
+            .and_then(|()| print_pools(&mut buf, &live.pools, threshold, color))
+            .is_err()
+        {
+            continue;
+        }
+        let mut stderr = stderr();
+        if tty {
+            erase(&mut stderr, live.lines).ok();
+            live.lines = String::from_utf8_lossy(&buf).lines().count();
+        }
+        stderr.write_all(&buf).ok();
+    }
+}
+
+/// Erases the last `lines` lines of the terminal.
+fn erase(out: &mut impl Write, lines: usize) -> io::Result<()> {
+    if lines > 0 {
+        write!(out, "\x1B[{}A\x1B[J", lines)?;
+    }
+    Ok(())
+}
+
+fn print_table(out: &mut impl Write, trace: &TraceMap, size: u32, color: Color) -> Result<()> {
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
     table.set_titles(row![
@@ -87,11 +462,95 @@ fn print_table(trace: &TraceMap, size: u32, color: Color) -> Result<()> {
         ]);
         used += size * entry.max;
     }
-    table.print(&mut stderr())?;
-    eprintln!();
-    eprintln!(
+    table.print(out)?;
+    writeln!(out)?;
+    writeln!(
+        out,
         "Maximum heap load: {}",
         color.bold(&format!("{} / {:.2}%", used, f64::from(used) / f64::from(size) * 100.0))
-    );
+    )?;
+    Ok(())
+}
+
+fn print_callers(callers: &CallerMap, symbols: Option<&Symbols>, color: Color) -> Result<()> {
+    let mut callers = callers.iter().collect::<Vec<_>>();
+    callers.sort_by(|(_, a), (_, b)| b.bytes.cmp(&a.bytes));
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        r->color.bold("Allocated Bytes"),
+        r->color.bold("Allocations"),
+        color.bold("Caller"),
+    ]);
+    for (&pc, entry) in callers {
+        table.add_row(row![r->entry.bytes, r->entry.count, caller_name(pc, symbols)]);
+    }
+    eprintln!();
+    table.print(&mut stderr())?;
+    Ok(())
+}
+
+fn caller_name(pc: u32, symbols: Option<&Symbols>) -> String {
+    symbols
+        .and_then(|symbols| symbols.lookup(pc))
+        .map_or_else(|| format!("0x{:08x}", pc), Into::into)
+}
+
+/// Prints the load of the configured pools. Pools with the current load at or
+/// above `threshold` percent of the capacity are flagged.
+fn print_pools(
+    out: &mut impl Write,
+    pools: &[PoolLoad],
+    threshold: u32,
+    color: Color,
+) -> Result<()> {
+    if pools.is_empty() {
+        return Ok(());
+    }
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        r->color.bold("Pool Block"),
+        r->color.bold("Capacity"),
+        r->color.bold("Current Load"),
+        r->color.bold("Max Load"),
+        color.bold("Status"),
+    ]);
+    for pool in pools {
+        let status = if pool.cur >= pool.capacity {
+            color.bold_fg("full", Red)
+        } else if u64::from(pool.cur) * 100 >= u64::from(pool.capacity) * u64::from(threshold) {
+            color.bold_fg("near capacity", Yellow)
+        } else {
+            String::new()
+        };
+        table.add_row(row![
+            r->format_size(pool.block),
+            r->pool.capacity,
+            r->pool.cur,
+            r->pool.max,
+            status,
+        ]);
+    }
+    writeln!(out)?;
+    table.print(out)?;
     Ok(())
 }
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.chunk.len() - self.position);
+        buf[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
@@ -1,29 +1,195 @@
 //! `drone log` command.
 
 use crate::{
-    cli::LogCmd,
+    cli::{LogCmd, LogProtocol},
     color::Color,
+    log,
+    log::{
+        health::{Health, Summary},
+        interned::Strings,
+        timeline::{exception_name, Timeline},
+        watch::Watch,
+    },
     probe,
     probe::{Log, Probe},
+    serial,
     templates::Registry,
-    utils::{register_signals, ser_to_string},
+    utils::{register_signals, ser_to_string, ExitCodeError},
 };
-use anyhow::{anyhow, Result};
+use ansi_term::Color::{Cyan, Red, Yellow};
+use anyhow::{bail, Result};
 use drone_config as config;
-use std::convert::TryFrom;
+use prettytable::{cell, format, row, Table};
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::stderr,
+    sync::{mpsc::channel, Arc},
+};
 
 /// Runs `drone log` command.
-pub fn run(cmd: LogCmd, color: Color) -> Result<()> {
-    let signals = register_signals()?;
-    let registry = Registry::new()?;
-    let config = config::Config::read_from_current_dir()?;
-    let probe = Probe::try_from(&config)?;
-    let log = Log::try_from(&config)?;
-    probe::log(probe, log).ok_or_else(|| {
-        anyhow!(
-            "`{}` log with `{}` probe is not supported",
-            ser_to_string(probe),
-            ser_to_string(log)
-        )
-    })?(cmd, signals, registry, config, color)
+pub fn run(mut cmd: LogCmd, color: Color) -> Result<()> {
+    let config = match config::Config::read_from_current_dir() {
+        Ok(config) => Some(config),
+        Err(_) if cmd.replay.is_some() => None,
+        Err(err) => return Err(err),
+    };
+    let core_clock =
+        config.as_ref().and_then(|config| config.log.as_ref()?.swo.as_ref()?.core_clock);
+    let exceptions = cmd.exceptions.clone();
+    let (dwt_events, dwt_receiver) = channel();
+    if usize::from(cmd.semihosting_port) >= log::PORTS_COUNT {
+        bail!("Port {} is out of range", cmd.semihosting_port);
+    }
+    let ports = config
+        .as_ref()
+        .and_then(|config| config.log.as_ref())
+        .map(|log| log.ports.clone())
+        .unwrap_or_default();
+    log::resolve_ports(&mut cmd.outputs, &ports)?;
+    let health = Arc::new(Health::default());
+    let interned = match (cmd.interned, &cmd.firmware) {
+        (Some(port), Some(firmware)) => Some(Arc::new(Strings::read(firmware, port)?)),
+        _ => None,
+    };
+    let watch = new_watch(&cmd, &health);
+    let options = log::Options {
+        timestamps: cmd.timestamps,
+        core_clock,
+        labels: cmd.labels,
+        port_names: ports.into_iter().map(|(name, port)| (port, name)).collect(),
+        format: cmd.format,
+        files: cmd.files.clone(),
+        color,
+        exception_trace: exceptions.is_some(),
+        dwt_events: exceptions.is_some().then_some(dwt_events),
+        record: cmd.record.clone(),
+        health: Arc::clone(&health),
+        interned,
+        watch: watch.clone(),
+        ..log::Options::default()
+    };
+    if let Some(replay) = &cmd.replay {
+        let protocol = match (cmd.protocol, &config) {
+            (Some(protocol), _) => protocol,
+            (None, Some(config)) => match Log::try_from(config)? {
+                Log::SwoProbe | Log::SwoSerial => LogProtocol::Swo,
+                Log::DsoSerial => LogProtocol::Dso,
+                Log::Rtt => bail!("`rtt` log can't be replayed, pass `--protocol` explicitly"),
+            },
+            (None, None) => bail!("`--protocol` is required outside of a Drone project"),
+        };
+        let parser = match protocol {
+            LogProtocol::Swo => log::swo::parser,
+            LogProtocol::Dso => log::dso::parser,
+        };
+        let outputs = log::Output::open_all(&cmd.outputs, &options)?;
+        log::replay(replay, &outputs, options, parser)?;
+    } else if let Some(mut config) = config {
+        serial::resolve_endpoints(&mut config)?;
+        let signals = register_signals()?;
+        let registry = Registry::new()?;
+        let probe = Probe::try_from(&config)?;
+        let log = Log::try_from(&config)?;
+        if cmd.stdin_port.is_some() && !matches!(log, Log::DsoSerial) {
+            bail!("`--stdin-port` is not supported by `{}` log", ser_to_string(log));
+        }
+        probe::check_rtt_channels(probe, log, &cmd.outputs)?;
+        probe::log_fn(probe, log)?(cmd, options, signals, registry, config, color)?;
+    }
+
+    print_link_health(&health.summary(), color)?;
+
+    if let Some(exceptions) = exceptions {
+        let mut timeline = Timeline::default();
+        for event in dwt_receiver.try_iter() {
+            timeline.push(&event);
+        }
+        timeline.write_chrome_trace(&mut File::create(&exceptions)?, core_clock)?;
+        print_exception_stats(&timeline, core_clock, color)?;
+        eprintln!(
+            "# {}: exception timeline written to `{}`",
+            color.bold_fg("hint", Cyan),
+            exceptions.display()
+        );
+    }
+
+    if let Some(watch) = watch {
+        check_watch(&watch, color)?;
+    }
+    Ok(())
+}
+
+fn new_watch(cmd: &LogCmd, health: &Arc<Health>) -> Option<Arc<Watch>> {
+    let LogCmd { until, fail_on, timeout, max_bytes, .. } = cmd;
+    (until.is_some() || fail_on.is_some() || timeout.is_some() || max_bytes.is_some()).then(|| {
+        Arc::new(Watch::new(
+            until.clone(),
+            fail_on.clone(),
+            *timeout,
+            max_bytes.map(u64::from),
+            Arc::clone(health),
+        ))
+    })
+}
+
+fn check_watch(watch: &Watch, color: Color) -> Result<()> {
+    if let Some(outcome) = watch.finish() {
+        let code = outcome.exit_code();
+        if code != 0 {
+            eprintln!("{}: {}", color.bold_fg("Error", Red), outcome);
+            bail!(ExitCodeError(code));
+        }
+        eprintln!("{}: {}", color.bold_fg("Passed", Cyan), outcome);
+    }
+    Ok(())
+}
+
+fn print_link_health(summary: &Summary, color: Color) -> Result<()> {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.add_row(row!["Bytes received", r->summary.bytes]);
+    table.add_row(row!["Packets decoded", r->summary.packets]);
+    table.add_row(row!["Bad packets", r->summary.bad_packets]);
+    table.add_row(row!["Dropped bytes", r->summary.dropped_bytes]);
+    table.add_row(row!["ITM overflows", r->summary.overflows]);
+    table.add_row(row!["Resynchronizations", r->summary.resyncs]);
+    table.add_row(row!["Output errors", r->summary.output_errors]);
+    eprintln!();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " LINK HEALTH "), Cyan));
+    table.print(&mut stderr())?;
+    if !summary.is_healthy() {
+        eprintln!(
+            "{}: the log link was unhealthy, some output may be lost.",
+            color.bold_fg("warning", Yellow)
+        );
+    }
+    Ok(())
+}
+
+fn print_exception_stats(timeline: &Timeline, core_clock: Option<u32>, color: Color) -> Result<()> {
+    let unit = if core_clock.is_some() { "us" } else { "cycles" };
+    let scale = core_clock.map_or(1.0, |core_clock| 1_000_000.0 / f64::from(core_clock));
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        color.bold("Exception"),
+        r->color.bold("Count"),
+        r->color.bold(&format!("Min, {}", unit)),
+        r->color.bold(&format!("Max, {}", unit)),
+        r->color.bold(&format!("Avg, {}", unit)),
+    ]);
+    for (number, stats) in timeline.stats() {
+        table.add_row(row![
+            exception_name(number),
+            r->stats.count,
+            r->format!("{:.2}", stats.min as f64 * scale),
+            r->format!("{:.2}", stats.max as f64 * scale),
+            r->format!("{:.2}", stats.avg() * scale),
+        ]);
+    }
+    eprintln!();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " EXCEPTIONS "), Cyan));
+    table.print(&mut stderr())?;
+    Ok(())
 }
@@ -6,10 +6,13 @@ pub mod gdb;
 pub mod heap;
 pub mod log;
 pub mod new;
+pub mod profile;
 pub mod reset;
 pub mod support;
+pub mod test;
 
 pub use self::{
     env::run as env, flash::run as flash, gdb::run as gdb, heap::run as heap, log::run as log,
-    new::run as new, reset::run as reset, support::run as support,
+    new::run as new, profile::run as profile, reset::run as reset, support::run as support,
+    test::run as test,
 };
@@ -0,0 +1,96 @@
+//! `drone profile` command.
+
+use crate::{
+    cli::{LogCmd, ProfileCmd},
+    color::Color,
+    log,
+    log::dwt,
+    probe,
+    probe::{Log, Probe},
+    profile::{Profile, Symbols},
+    serial,
+    templates::Registry,
+    utils::{register_signals, ser_to_string},
+};
+use ansi_term::Color::{Cyan, Yellow};
+use anyhow::{bail, Result};
+use drone_config as config;
+use prettytable::{cell, format, row, Table};
+use std::{convert::TryFrom, fs::File, io::stderr, sync::mpsc::channel};
+
+/// Runs `drone profile` command.
+pub fn run(cmd: ProfileCmd, color: Color) -> Result<()> {
+    let ProfileCmd { firmware, reset, folded, flat, mut outputs } = cmd;
+    let signals = register_signals()?;
+    let registry = Registry::new()?;
+    let mut config = config::Config::read_from_current_dir()?;
+    serial::resolve_endpoints(&mut config)?;
+    let probe = Probe::try_from(&config)?;
+    let log = Log::try_from(&config)?;
+    if let Log::DsoSerial | Log::Rtt = log {
+        bail!("PC sampling requires SWO, but `{}` log is configured", ser_to_string(log));
+    }
+    let log_fn = probe::log_fn(probe, log)?;
+    if let Some(config_log) = &config.log {
+        log::resolve_ports(&mut outputs, &config_log.ports)?;
+    }
+    let symbols = Symbols::read(&firmware)?;
+
+    let (dwt_events, samples) = channel();
+    let options =
+        log::Options { pc_sampling: true, dwt_events: Some(dwt_events), ..log::Options::default() };
+    let cmd = LogCmd { reset, outputs, ..LogCmd::default() };
+    log_fn(cmd, options, signals, registry, config, color)?;
+
+    let mut profile = Profile::default();
+    for event in samples.try_iter() {
+        if let dwt::Packet::PcSample { pc } = event.packet {
+            profile.add(&symbols, pc);
+        }
+    }
+    if profile.total() == 0 {
+        eprintln!("{}: no PC samples received.", color.bold_fg("warning", Yellow));
+        return Ok(());
+    }
+    print_table(&profile, color)?;
+    if let Some(folded) = folded {
+        profile.write_folded(&mut File::create(&folded)?)?;
+        eprintln!(
+            "# {}: folded stacks written to `{}`",
+            color.bold_fg("hint", Cyan),
+            folded.display()
+        );
+    }
+    if let Some(flat) = flat {
+        profile.write_flat(&mut File::create(&flat)?)?;
+        eprintln!(
+            "# {}: flat profile written to `{}`",
+            color.bold_fg("hint", Cyan),
+            flat.display()
+        );
+    }
+    Ok(())
+}
+
+fn print_table(profile: &Profile, color: Color) -> Result<()> {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        r->color.bold("Samples"),
+        r->color.bold("Percent"),
+        color.bold("Function"),
+    ]);
+    for (name, count) in profile.flat() {
+        table.add_row(row![
+            r->count,
+            r->format!("{:.2}%", f64::from(count) / f64::from(profile.total()) * 100.0),
+            name,
+        ]);
+    }
+    eprintln!();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " PROFILE "), Cyan));
+    table.print(&mut stderr())?;
+    eprintln!();
+    eprintln!("Total samples: {}", color.bold(&profile.total().to_string()));
+    Ok(())
+}
@@ -59,6 +59,9 @@ fn probe_cell(probe: Option<Probe>, log_swo: bool, log_dso: bool, color: Color)
         if log_dso && probe::log(probe, Log::DsoSerial).is_some() {
             logs.push(color.bold("dsoserial"));
         }
+        if probe::log(probe, Log::Rtt).is_some() {
+            logs.push(color.bold("rtt"));
+        }
         Ok(format!("--log {}", logs.join("/")))
     } else {
         Ok("--".into())
@@ -0,0 +1,215 @@
+//! `drone test` command.
+
+use crate::{
+    cli::{FlashCmd, LogCmd, LogOutput, LogSink, TestCmd},
+    cmd::env::env_command,
+    color::Color,
+    log,
+    log::{
+        health::Health,
+        watch::{Outcome, Watch},
+    },
+    probe,
+    probe::{Log, Probe},
+    serial,
+    templates::Registry,
+    test::{Event, Report},
+    utils::{register_signals, ExitCodeError},
+};
+use ansi_term::Color::{Green, Red, Yellow};
+use anyhow::{anyhow, bail, Result};
+use drone_config as config;
+use serde_json::Value;
+use std::{
+    convert::TryFrom,
+    env, mem,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{mpsc::channel, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Exit status of a failed test run, the same as of `cargo test`.
+const FAILURE_EXIT_CODE: i32 = 101;
+
+/// Runs `drone test` command.
+pub fn run(cmd: TestCmd, color: Color) -> Result<()> {
+    let TestCmd { firmware, target, features, port, console, timeout, args } = cmd;
+    if usize::from(port) >= log::PORTS_COUNT {
+        bail!("Port {} is out of range", port);
+    }
+    if !args.is_empty() {
+        eprintln!(
+            "{}: ignoring test harness arguments {:?}",
+            color.bold_fg("warning", Yellow),
+            args
+        );
+    }
+    let mut config = config::Config::read_from_current_dir()?;
+    serial::resolve_endpoints(&mut config)?;
+    let probe = Probe::try_from(&config)?;
+    let log = Log::try_from(&config)?;
+    probe::log_fn(probe, log)?;
+    probe::check_rtt_channels(probe, log, &outputs(port, &console))?;
+    let firmwares = match firmware {
+        Some(firmware) => vec![firmware],
+        None => build(target, features)?,
+    };
+    let mut success = true;
+    for firmware in &firmwares {
+        let report = run_binary(firmware, &config, probe, log, port, &console, timeout, color)?;
+        print_report(&report, color);
+        success &= report.is_success();
+    }
+    if !success {
+        bail!(ExitCodeError(FAILURE_EXIT_CODE));
+    }
+    Ok(())
+}
+
+/// Builds the test binaries with `cargo test` for `target`.
+fn build(target: Option<String>, features: Option<String>) -> Result<Vec<PathBuf>> {
+    let target = target
+        .or_else(|| env::var("CARGO_BUILD_TARGET").ok())
+        .ok_or_else(|| anyhow!("`--target` is required to build the tests"))?;
+    let mut cargo = env_command(&target, "cargo");
+    cargo.arg("test").arg("--no-run").arg("--message-format=json");
+    if let Some(features) = features {
+        cargo.arg("--features").arg(features);
+    }
+    cargo.stderr(Stdio::inherit());
+    let output = cargo.output()?;
+    if !output.status.success() {
+        bail!("`{:?}` failed", cargo);
+    }
+    let mut binaries = Vec::new();
+    for line in String::from_utf8(output.stdout)?.lines() {
+        let message = match serde_json::from_str::<Value>(line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if message["reason"] == "compiler-artifact" && message["profile"]["test"] == true {
+            if let Some(executable) = message["executable"].as_str() {
+                binaries.push(executable.into());
+            }
+        }
+    }
+    if binaries.is_empty() {
+        bail!("`{:?}` didn't produce any test binaries", cargo);
+    }
+    Ok(binaries)
+}
+
+/// Returns log outputs for the console `ports` and the results `port`.
+fn outputs(port: u8, console: &[u32]) -> Vec<LogOutput> {
+    vec![
+        LogOutput { ports: console.to_vec(), names: Vec::new(), sink: LogSink::Stdout },
+        LogOutput { ports: vec![u32::from(port)], names: Vec::new(), sink: LogSink::Discard },
+    ]
+}
+
+/// Flashes the test binary and collects the test results from the log.
+#[allow(clippy::too_many_arguments)]
+fn run_binary(
+    firmware: &Path,
+    config: &config::Config,
+    probe: Probe,
+    log: Log,
+    port: u8,
+    console: &[u32],
+    timeout: Duration,
+    color: Color,
+) -> Result<Report> {
+    eprintln!();
+    eprintln!("{} {}", color.bold_fg("Running", Green), firmware.display());
+    let flash_cmd = FlashCmd { firmware: firmware.to_path_buf() };
+    probe::flash(probe)(flash_cmd, register_signals()?, Registry::new()?, config.clone())?;
+
+    let health = Arc::new(Health::default());
+    let watch = Arc::new(Watch::new(None, None, Some(timeout), None, Arc::clone(&health)));
+    let report = Arc::new(Mutex::new(Report::default()));
+    let (lines, lines_receiver) = channel::<String>();
+    thread::spawn({
+        let report = Arc::clone(&report);
+        let watch = Arc::clone(&watch);
+        move || {
+            for line in lines_receiver {
+                if let Some(event) = Event::parse(&line) {
+                    print_event(&event, color);
+                    let summary = matches!(event, Event::Summary { .. });
+                    report.lock().unwrap().push(event);
+                    if summary {
+                        watch.stop(Outcome::Passed(line));
+                    }
+                }
+            }
+        }
+    });
+
+    let options = log::Options {
+        health,
+        watch: Some(Arc::clone(&watch)),
+        port_lines: Some((port, lines)),
+        ..log::Options::default()
+    };
+    let cmd = LogCmd {
+        reset: true,
+        firmware: Some(firmware.to_path_buf()),
+        outputs: outputs(port, console),
+        ..LogCmd::default()
+    };
+    let log_fn = probe::log_fn(probe, log)?;
+    log_fn(cmd, options, register_signals()?, Registry::new()?, config.clone(), color)?;
+
+    let mut report = mem::take(&mut *report.lock().unwrap());
+    if let Some(Outcome::TimedOut(timeout)) = watch.outcome() {
+        report.abort(&format!("timed out after {:?}", timeout));
+        eprintln!("{}: test run timed out after {:?}", color.bold_fg("error", Red), timeout);
+    }
+    Ok(report)
+}
+
+fn print_event(event: &Event, color: Color) {
+    match event {
+        Event::Started(_) | Event::Summary { .. } => {}
+        Event::Passed(name) => println!("test {} ... {}", name, color.bold_fg("ok", Green)),
+        Event::Failed(name, _) => println!("test {} ... {}", name, color.bold_fg("FAILED", Red)),
+        Event::Ignored(name) => println!("test {} ... {}", name, color.bold_fg("ignored", Yellow)),
+    }
+}
+
+fn print_report(report: &Report, color: Color) {
+    if !report.failed.is_empty() {
+        println!();
+        println!("failures:");
+        for (name, message) in &report.failed {
+            if message.is_empty() {
+                println!("    {}", name);
+            } else {
+                println!("    {}: {}", name, message);
+            }
+        }
+    }
+    let result =
+        if report.is_success() { color.bold_fg("ok", Green) } else { color.bold_fg("FAILED", Red) };
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed; {} ignored",
+        result,
+        report.passed.len(),
+        report.failed.len(),
+        report.ignored.len()
+    );
+    if report.summary.is_none() {
+        eprintln!(
+            "{}: the target didn't report the test summary on the results port.",
+            color.bold_fg("warning", Yellow)
+        );
+    } else if !report.is_consistent() {
+        eprintln!(
+            "{}: the summary reported by the target doesn't match the test results.",
+            color.bold_fg("warning", Yellow)
+        );
+    }
+}
@@ -16,6 +16,12 @@ pub enum Color {
     Auto,
 }
 
+impl Default for Color {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
 impl Color {
     /// Attempts to colorize `text` and make it bold.
     pub fn bold_fg(self, text: &str, colour: Colour) -> String {
@@ -1,9 +1,9 @@
 //! Heap layout generation.
 
 use super::TraceMap;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use drone_config::format_size;
-use std::io::Write;
+use std::{convert::TryFrom, io::Write};
 
 const WORD_SIZE: u32 = 4;
 
@@ -22,8 +22,8 @@ pub fn empty(size: u32, pools: u32) -> Vec<(u32, u32)> {
         if block <= prev_block {
             block = prev_block + WORD_SIZE;
         }
-        let capacity = add_capacity(block, size - used, ratio, f64::from(size));
-        used += block * capacity;
+        let capacity = add_capacity(block, u64::from(size) - used, ratio, f64::from(size));
+        used += u64::from(block) * u64::from(capacity);
         prev_block = block;
         layout.push((block, capacity));
     }
@@ -31,10 +31,32 @@ pub fn empty(size: u32, pools: u32) -> Vec<(u32, u32)> {
     layout
 }
 
+/// Layout optimization objective.
+#[derive(Clone, Copy, Debug)]
+pub enum Objective {
+    /// Minimal fragmentation for the fixed heap size.
+    Fragmentation {
+        /// Heap size.
+        size: u32,
+    },
+    /// Minimal heap size fitting the trace load increased by the margin.
+    Size {
+        /// Safety margin in percent of the trace load.
+        margin: u32,
+    },
+}
+
 /// Creates an optimized layout based on heaptrace.
-pub fn optimize(trace: &TraceMap, size: u32, mut pools: u32) -> Result<(Vec<(u32, u32)>, u32)> {
+///
+/// The block sizes are partitioned into at most `pools` pools of contiguous
+/// sizes by dynamic programming, which runs in `O(pools * sizes^2)` time.
+/// Returns the layout and its fragmentation in bytes.
+pub fn optimize(
+    trace: &TraceMap,
+    pools: u32,
+    objective: Objective,
+) -> Result<(Vec<(u32, u32)>, u32)> {
     let mut input = Vec::<(u32, u32)>::with_capacity(trace.len());
-    let mut used = 0;
     let mut prev_size = 0;
     for (size, entry) in trace {
         let size = align(*size);
@@ -44,22 +66,59 @@ pub fn optimize(trace: &TraceMap, size: u32, mut pools: u32) -> Result<(Vec<(u32
             input.push((size, entry.max));
             prev_size = size;
         }
-        used += size * entry.max;
     }
-    if (input.len() as u32) < pools {
-        pools = input.len() as u32;
+    if input.is_empty() {
+        bail!("Trace file is empty");
+    }
+    let pools = (pools as usize).min(input.len()).max(1);
+    let mut count_sums = vec![0_u64; input.len() + 1];
+    let mut byte_sums = vec![0_u64; input.len() + 1];
+    for (i, &(block, capacity)) in input.iter().enumerate() {
+        count_sums[i + 1] = count_sums[i] + u64::from(capacity);
+        byte_sums[i + 1] = byte_sums[i] + u64::from(block) * u64::from(capacity);
     }
-    let mut output = Vec::with_capacity(pools as usize);
-    output.resize_with(output.capacity(), Default::default);
+    let capacity = |start: usize, end: usize| {
+        let count = count_sums[end] - count_sums[start];
+        match objective {
+            Objective::Fragmentation { .. } => count,
+            Objective::Size { margin } => (count * u64::from(100 + margin) + 99) / 100,
+        }
+    };
+    let cost = |start: usize, end: usize| match objective {
+        Objective::Fragmentation { .. } => {
+            u64::from(input[end - 1].0) * capacity(start, end) - (byte_sums[end] - byte_sums[start])
+        }
+        Objective::Size { .. } => u64::from(input[end - 1].0) * capacity(start, end),
+    };
+    let mut output = Vec::with_capacity(pools);
     let mut frag = 0;
-    shrink(&input, &mut output, &mut frag, size - used);
-    extend(&mut output, size);
-    Ok((output, frag))
+    let mut start = 0;
+    for end in partition(input.len(), pools, cost) {
+        let block = input[end - 1].0;
+        frag += u64::from(block) * (count_sums[end] - count_sums[start])
+            - (byte_sums[end] - byte_sums[start]);
+        output.push((block, u32::try_from(capacity(start, end))?));
+        start = end;
+    }
+    if let Objective::Fragmentation { size } = objective {
+        let used = total(&output);
+        if used > u64::from(size) {
+            bail!(
+                "The trace needs at least {} bytes in {} pools, but the heap size is {}",
+                used,
+                pools,
+                size
+            );
+        }
+        extend(&mut output, used, size);
+    }
+    Ok((output, u32::try_from(frag)?))
 }
 
 /// Renders `[heap]` section for `Drone.toml`.
 pub fn render(w: &mut impl Write, layout: &[(u32, u32)]) -> Result<()> {
-    let size = layout.iter().map(|(size, count)| size * count).sum::<u32>();
+    let size = u32::try_from(total(layout))
+        .map_err(|_| anyhow!("The layout doesn't fit in the 32-bit address space"))?;
     writeln!(w, "[heap]")?;
     writeln!(w, "size = \"{}\"", format_size(size))?;
     writeln!(w, "pools = [")?;
@@ -73,47 +132,49 @@ pub fn render(w: &mut impl Write, layout: &[(u32, u32)]) -> Result<()> {
     Ok(())
 }
 
-fn shrink(input: &[(u32, u32)], output: &mut [(u32, u32)], frag: &mut u32, cutoff: u32) {
-    if output.len() == 1 {
-        let (max_block, mut total) = input[input.len() - 1];
-        for (block, capacity) in &input[..input.len() - 1] {
-            *frag += (max_block - block) * capacity;
-            total += capacity;
-        }
-        output[0] = (max_block, total);
-    } else {
-        let (mut opt_i, mut opt_frag) = (0, cutoff);
-        for i in 0..input.len() - output.len() {
-            let mut cur_frag = *frag;
-            let (max_block, _) = input[i];
-            for (block, capacity) in input.iter().take(i) {
-                cur_frag += (max_block - block) * capacity;
-            }
-            if cur_frag < opt_frag {
-                shrink(&input[i + 1..], &mut output[1..], &mut cur_frag, opt_frag);
-                if cur_frag <= opt_frag {
-                    opt_i = i;
-                    opt_frag = cur_frag;
+/// Partitions `len` items into `groups` non-empty contiguous groups with the
+/// minimal total cost. Returns the end index of each group.
+fn partition(len: usize, groups: usize, cost: impl Fn(usize, usize) -> u64) -> Vec<usize> {
+    // `best[j][i]` is the minimal cost of splitting the first `i` items into
+    // `j` groups, and `split[j][i]` is the start of the last group.
+    let mut best = vec![vec![u64::max_value(); len + 1]; groups + 1];
+    let mut split = vec![vec![0; len + 1]; groups + 1];
+    best[0][0] = 0;
+    for j in 1..=groups {
+        for i in j..=len {
+            for start in j - 1..i {
+                if best[j - 1][start] == u64::max_value() {
+                    continue;
+                }
+                let total = best[j - 1][start] + cost(start, i);
+                if total < best[j][i] {
+                    best[j][i] = total;
+                    split[j][i] = start;
                 }
             }
         }
-        let (max_block, mut total) = input[opt_i];
-        for (block, capacity) in input.iter().take(opt_i) {
-            *frag += (max_block - block) * capacity;
-            total += capacity;
-        }
-        output[0] = (max_block, total);
-        shrink(&input[opt_i + 1..], &mut output[1..], frag, opt_frag);
     }
+    let mut ends = Vec::with_capacity(groups);
+    let mut end = len;
+    for j in (1..=groups).rev() {
+        ends.push(end);
+        end = split[j][end];
+    }
+    ends.reverse();
+    ends
 }
 
-fn extend(output: &mut [(u32, u32)], size: u32) {
-    let mut used = output.iter().map(|(block, capacity)| block * capacity).sum::<u32>();
+/// Returns the total size of `layout` in bytes.
+pub fn total(layout: &[(u32, u32)]) -> u64 {
+    layout.iter().map(|&(block, capacity)| u64::from(block) * u64::from(capacity)).sum()
+}
+
+fn extend(output: &mut [(u32, u32)], mut used: u64, size: u32) {
     let count = output.len() as u32;
-    let free = f64::from(size - used);
+    let free = (u64::from(size) - used) as f64;
     for ((block, capacity), ratio) in output.iter_mut().zip(ratios(count)) {
-        let add = add_capacity(*block, size - used, ratio, free);
-        used += add * *block;
+        let add = add_capacity(*block, u64::from(size) - used, ratio, free);
+        used += u64::from(add) * u64::from(*block);
         *capacity += add;
     }
     add_up_to_size(output, &mut used, size);
@@ -135,19 +196,20 @@ fn ratios(n: u32) -> Vec<f64> {
     ratios
 }
 
-fn add_capacity(block: u32, free: u32, ratio: f64, total: f64) -> u32 {
+fn add_capacity(block: u32, free: u64, ratio: f64, total: f64) -> u32 {
     let mut capacity = (total / f64::from(block) * ratio).round() as u32;
-    if block * capacity > free {
-        capacity -= (f64::from(block * capacity - free) / f64::from(block)).ceil() as u32;
+    let bytes = u64::from(block) * u64::from(capacity);
+    if bytes > free {
+        capacity -= ((bytes - free) as f64 / f64::from(block)).ceil() as u32;
     }
     capacity
 }
 
-fn add_up_to_size(layout: &mut [(u32, u32)], used: &mut u32, size: u32) {
+fn add_up_to_size(layout: &mut [(u32, u32)], used: &mut u64, size: u32) {
     for (block, capacity) in layout.iter_mut().rev() {
-        let add = (size - *used) / *block;
-        *used += add * *block;
-        *capacity += add;
+        let add = (u64::from(size) - *used) / u64::from(*block);
+        *used += add * u64::from(*block);
+        *capacity += add as u32;
     }
 }
 
@@ -157,3 +219,48 @@ fn align(mut value: u32) -> u32 {
     }
     value
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::TraceEntry;
+
+    fn trace(entries: &[(u32, u32)]) -> TraceMap {
+        entries
+            .iter()
+            .map(|&(size, max)| (size, TraceEntry { max, ..TraceEntry::default() }))
+            .collect()
+    }
+
+    #[test]
+    fn single_size_class() {
+        let (layout, frag) =
+            optimize(&trace(&[(16, 3)]), 4, Objective::Fragmentation { size: 1024 }).unwrap();
+        assert_eq!(layout, vec![(16, 64)]);
+        assert_eq!(frag, 0);
+    }
+
+    #[test]
+    fn more_size_classes_than_pools() {
+        let trace = trace(&[(4, 1), (8, 1), (12, 1), (16, 1)]);
+        let (layout, frag) = optimize(&trace, 2, Objective::Size { margin: 0 }).unwrap();
+        assert_eq!(layout, vec![(8, 2), (16, 2)]);
+        assert_eq!(frag, 8);
+    }
+
+    #[test]
+    fn size_margin_rounds_up() {
+        let trace = trace(&[(8, 3)]);
+        let (layout, _) = optimize(&trace, 1, Objective::Size { margin: 0 }).unwrap();
+        assert_eq!(layout, vec![(8, 3)]);
+        let (layout, _) = optimize(&trace, 1, Objective::Size { margin: 10 }).unwrap();
+        assert_eq!(layout, vec![(8, 4)]);
+    }
+
+    #[test]
+    fn exceeds_heap_size() {
+        let err =
+            optimize(&trace(&[(64, 10)]), 2, Objective::Fragmentation { size: 256 }).unwrap_err();
+        assert!(err.to_string().contains("at least 640 bytes"));
+    }
+}
@@ -0,0 +1,125 @@
+//! Heap leak detection.
+
+use super::{apply, trace::Packet, TraceMap};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+/// Number of windows at the start of the trace treated as warm-up by the
+/// steady-state check.
+const WARM_UP_WINDOWS: usize = 1;
+
+/// Number of increases of the window minima flagging a growth.
+const MIN_INCREASES: usize = 2;
+
+/// Outstanding allocations of a block size by a caller.
+pub struct Leak {
+    /// Block size.
+    pub size: u32,
+    /// Caller PC, if traced.
+    pub caller: Option<u32>,
+    /// Number of outstanding blocks.
+    pub count: u32,
+}
+
+/// Live block count of a size over the trace.
+pub struct Growth {
+    /// Block size.
+    pub size: u32,
+    /// Minimum live block count within each window of the trace.
+    pub minima: Vec<u32>,
+}
+
+impl Leak {
+    /// Returns the number of bytes held by the outstanding blocks.
+    pub fn bytes(&self) -> u64 {
+        u64::from(self.size) * u64::from(self.count)
+    }
+}
+
+impl Growth {
+    /// Returns `true` if the live block count keeps growing after the warm-up,
+    /// i.e. the window minima past the first window never decrease and
+    /// increase at least twice.
+    ///
+    /// Blocks allocated at startup and held forever raise the minimum once,
+    /// during the warm-up, and are not reported.
+    pub fn is_growing(&self) -> bool {
+        let minima = self.minima.get(WARM_UP_WINDOWS..).unwrap_or_default();
+        minima.windows(2).all(|pair| pair[0] <= pair[1])
+            && minima.windows(2).filter(|pair| pair[0] < pair[1]).count() >= MIN_INCREASES
+    }
+}
+
+/// Returns the allocations outstanding at the end of the trace, ordered by
+/// bytes held.
+///
+/// Allocations are attributed to callers by the block address, which is
+/// carried by version 2 packets only. The rest of the outstanding blocks are
+/// reported without a caller.
+pub fn outstanding(packets: &[Packet], max_size: u32) -> Result<Vec<Leak>> {
+    let mut trace = TraceMap::new();
+    let mut blocks = HashMap::new();
+    for &packet in packets {
+        apply(&mut trace, &mut [], packet, max_size)?;
+        match packet {
+            Packet::Alloc { size, site: Some(site) } => {
+                blocks.insert(site.address, (size, site.caller));
+            }
+            Packet::Dealloc { site: Some(site), .. } => {
+                blocks.remove(&site.address);
+            }
+            Packet::Grow { new_size, site: Some(site), .. }
+            | Packet::Shrink { new_size, site: Some(site), .. } => {
+                if let Some((size, _)) = blocks.get_mut(&site.address) {
+                    *size = new_size;
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut attributed = BTreeMap::<(u32, u32), u32>::new();
+    for &(size, caller) in blocks.values() {
+        *attributed.entry((size, caller)).or_default() += 1;
+    }
+    let mut leaks = Vec::new();
+    for (&size, entry) in &trace {
+        let mut unattributed = entry.cur;
+        for (&(_, caller), &count) in attributed.range((size, 0)..=(size, u32::MAX)) {
+            let count = count.min(unattributed);
+            unattributed -= count;
+            leaks.push(Leak { size, caller: Some(caller), count });
+        }
+        if unattributed > 0 {
+            leaks.push(Leak { size, caller: None, count: unattributed });
+        }
+    }
+    leaks.retain(|leak| leak.count > 0);
+    leaks.sort_by(|a, b| b.bytes().cmp(&a.bytes()).then(a.size.cmp(&b.size)));
+    Ok(leaks)
+}
+
+/// Splits the trace into `windows` windows of equal number of packets and
+/// returns the minimum live block count of each size within each window.
+pub fn growth(packets: &[Packet], max_size: u32, windows: usize) -> Result<Vec<Growth>> {
+    let windows = windows.min(packets.len()).max(1);
+    let mut trace = TraceMap::new();
+    let mut minima = BTreeMap::<u32, Vec<u32>>::new();
+    for window in 0..windows {
+        let start = window * packets.len() / windows;
+        let end = (window + 1) * packets.len() / windows;
+        let mut window_min =
+            trace.iter().map(|(&size, entry)| (size, entry.cur)).collect::<BTreeMap<_, _>>();
+        for &packet in &packets[start..end] {
+            apply(&mut trace, &mut [], packet, max_size)?;
+            for size in packet.sizes().iter().flatten() {
+                let cur = trace.get(size).map_or(0, |entry| entry.cur);
+                let min = window_min.entry(*size).or_insert(0);
+                *min = (*min).min(cur);
+            }
+        }
+        for (size, min) in window_min {
+            minima.entry(size).or_insert_with(|| vec![0; window]).push(min);
+        }
+    }
+    Ok(minima.into_iter().map(|(size, minima)| Growth { size, minima }).collect())
+}
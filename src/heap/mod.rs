@@ -1,10 +1,14 @@
 //! Heap layout management.
 
 pub mod layout;
+pub mod leaks;
+pub mod simulate;
+pub mod timeline;
 pub mod trace;
 
 use self::trace::{Packet, Parser};
 use anyhow::{bail, Result};
+use drone_config::HeapPool;
 use std::{collections::BTreeMap, fs::File};
 
 /// Processed trace map.
@@ -21,28 +25,94 @@ pub struct TraceEntry {
     pub total: u32,
 }
 
+/// Allocations attributed to caller PCs.
+pub type CallerMap = BTreeMap<u32, CallerEntry>;
+
+/// Allocations made by a caller.
+#[derive(Default)]
+pub struct CallerEntry {
+    /// Number of allocations.
+    pub count: u32,
+    /// Total allocated bytes.
+    pub bytes: u64,
+}
+
+/// Load of a configured heap pool.
+#[derive(Clone)]
+pub struct PoolLoad {
+    /// Block size of the pool.
+    pub block: u32,
+    /// Number of blocks in the pool.
+    pub capacity: u32,
+    /// Currently allocated blocks.
+    pub cur: u32,
+    /// Maximum allocated blocks.
+    pub max: u32,
+}
+
 /// Reads the trace file.
 pub fn read_trace(trace: &mut TraceMap, trace_file: File, max_size: u32) -> Result<()> {
     let parser = Parser::new(trace_file)?;
     for packet in parser {
-        let packet = packet?;
-        match packet {
-            Packet::Alloc { size } => {
-                alloc(trace, size, max_size)?;
-            }
-            Packet::Dealloc { size } => {
-                dealloc(trace, size)?;
-            }
-            Packet::Grow { old_size, new_size } | Packet::Shrink { old_size, new_size } => {
-                dealloc(trace, old_size)?;
-                alloc(trace, new_size, max_size)?;
-            }
+        apply(trace, &mut [], packet?, max_size)?;
+    }
+    Ok(())
+}
+
+/// Reads all packets from the trace file.
+pub fn read_packets(trace_file: File) -> Result<Vec<Packet>> {
+    Ok(Parser::new(trace_file)?.collect::<Result<_, _>>()?)
+}
+
+/// Reads the allocation callers from the trace file. Only version 2 packets
+/// carry the callers.
+pub fn read_callers(callers: &mut CallerMap, trace_file: File) -> Result<()> {
+    let parser = Parser::new(trace_file)?;
+    for packet in parser {
+        if let Packet::Alloc { size, site: Some(site) } = packet? {
+            let entry = callers.entry(site.caller).or_default();
+            entry.count += 1;
+            entry.bytes += u64::from(size);
+        }
+    }
+    Ok(())
+}
+
+/// Creates load counters for the configured `pools`, ordered by block size.
+pub fn pool_loads(pools: &[HeapPool]) -> Vec<PoolLoad> {
+    let mut loads = pools
+        .iter()
+        .map(|pool| PoolLoad { block: pool.block, capacity: pool.capacity, cur: 0, max: 0 })
+        .collect::<Vec<_>>();
+    loads.sort_by_key(|load| load.block);
+    loads
+}
+
+/// Applies a trace `packet` to the `trace` map and the `pools` load counters.
+///
+/// An allocation is accounted to the smallest pool fitting the block.
+pub fn apply(
+    trace: &mut TraceMap,
+    pools: &mut [PoolLoad],
+    packet: Packet,
+    max_size: u32,
+) -> Result<()> {
+    match packet {
+        Packet::Alloc { size, .. } => {
+            alloc(trace, pools, size, max_size)?;
+        }
+        Packet::Dealloc { size, .. } => {
+            dealloc(trace, pools, size)?;
+        }
+        Packet::Grow { old_size, new_size, .. } | Packet::Shrink { old_size, new_size, .. } => {
+            dealloc(trace, pools, old_size)?;
+            alloc(trace, pools, new_size, max_size)?;
         }
     }
     Ok(())
 }
 
-fn alloc(trace: &mut TraceMap, size: u32, max_size: u32) -> Result<()> {
+fn alloc(trace: &mut TraceMap, pools: &mut [PoolLoad], size: u32, max_size: u32) -> Result<()> {
     if size > max_size {
         bail!("Trace file is corrupted");
     }
@@ -52,14 +122,23 @@ fn alloc(trace: &mut TraceMap, size: u32, max_size: u32) -> Result<()> {
     if entry.max < entry.cur {
         entry.max = entry.cur;
     }
+    if let Some(pool) = pools.iter_mut().find(|pool| pool.block >= size) {
+        pool.cur += 1;
+        if pool.max < pool.cur {
+            pool.max = pool.cur;
+        }
+    }
     Ok(())
 }
 
-fn dealloc(trace: &mut TraceMap, size: u32) -> Result<()> {
+fn dealloc(trace: &mut TraceMap, pools: &mut [PoolLoad], size: u32) -> Result<()> {
     let entry = trace.entry(size).or_default();
     if entry.cur == 0 {
         bail!("Trace file is corrupted");
     }
     entry.cur -= 1;
+    if let Some(pool) = pools.iter_mut().find(|pool| pool.block >= size) {
+        pool.cur = pool.cur.saturating_sub(1);
+    }
     Ok(())
 }
@@ -0,0 +1,152 @@
+//! Heap trace simulation against a pool layout.
+
+use super::trace::{Packet, Site};
+use drone_config::HeapPool;
+use std::collections::HashMap;
+
+/// Result of a heap trace simulation.
+pub struct Simulation {
+    /// Usage of each pool, ordered by block size.
+    pub pools: Vec<PoolUsage>,
+    /// Number of allocations.
+    pub allocations: u32,
+    /// Number of allocations served by a pool larger than the best fitting
+    /// one.
+    pub spills: u32,
+    /// Number of failed allocations.
+    pub failures: u32,
+    /// The first failed allocation.
+    pub first_failure: Option<Failure>,
+}
+
+/// Usage of a pool during the simulation.
+pub struct PoolUsage {
+    /// Block size of the pool.
+    pub block: u32,
+    /// Number of blocks in the pool.
+    pub capacity: u32,
+    /// Maximum number of allocated blocks.
+    pub peak: u32,
+    /// Number of allocations spilled into the pool from smaller pools.
+    pub spilled_in: u32,
+    used: u32,
+}
+
+/// Failed allocation.
+pub struct Failure {
+    /// Index of the trace event, starting from 1.
+    pub event: usize,
+    /// Requested block size.
+    pub size: u32,
+    /// Block location, if traced.
+    pub site: Option<Site>,
+}
+
+/// Live blocks of the simulated heap.
+#[derive(Default)]
+struct Blocks {
+    /// Pools of the blocks with traced addresses.
+    by_address: HashMap<u32, usize>,
+    /// Pools of the blocks without traced addresses, by size.
+    by_size: HashMap<u32, Vec<usize>>,
+    /// Number of failed allocations not yet deallocated, by size.
+    failed: HashMap<u32, u32>,
+}
+
+/// Replays the trace `packets` against the `pools` layout.
+///
+/// An allocation is served by the smallest pool fitting the block, or by the
+/// next larger pool with a free block, as the firmware allocator does. A
+/// deallocation releases the block with the same address if traced, or the
+/// most recent allocation of the same size otherwise.
+pub fn simulate(packets: &[Packet], pools: &[HeapPool]) -> Simulation {
+    let mut pools = pools
+        .iter()
+        .map(|pool| PoolUsage {
+            block: pool.block,
+            capacity: pool.capacity,
+            peak: 0,
+            spilled_in: 0,
+            used: 0,
+        })
+        .collect::<Vec<_>>();
+    pools.sort_by_key(|pool| pool.block);
+    let mut simulation =
+        Simulation { pools, allocations: 0, spills: 0, failures: 0, first_failure: None };
+    let mut blocks = Blocks::default();
+    for (index, &packet) in packets.iter().enumerate() {
+        match packet {
+            Packet::Alloc { size, site } => {
+                simulation.alloc(&mut blocks, index + 1, size, site);
+            }
+            Packet::Dealloc { size, site } => {
+                simulation.dealloc(&mut blocks, size, site);
+            }
+            Packet::Grow { old_size, new_size, site }
+            | Packet::Shrink { old_size, new_size, site } => {
+                if site.is_none() {
+                    if let Some(pool) = blocks.by_size.get_mut(&old_size).and_then(Vec::pop) {
+                        blocks.by_size.entry(new_size).or_default().push(pool);
+                    } else if let Some(failed) = blocks.failed.get_mut(&old_size) {
+                        *failed = failed.saturating_sub(1);
+                        *blocks.failed.entry(new_size).or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+    simulation
+}
+
+impl Simulation {
+    fn alloc(&mut self, blocks: &mut Blocks, event: usize, size: u32, site: Option<Site>) {
+        self.allocations += 1;
+        let fitting = self.pools.iter().position(|pool| pool.block >= size);
+        let served = fitting.and_then(|fitting| {
+            (fitting..self.pools.len())
+                .find(|&index| self.pools[index].used < self.pools[index].capacity)
+        });
+        let (fitting, index) = if let (Some(fitting), Some(index)) = (fitting, served) {
+            (fitting, index)
+        } else {
+            self.failures += 1;
+            if self.first_failure.is_none() {
+                self.first_failure = Some(Failure { event, size, site });
+            }
+            if site.is_none() {
+                *blocks.failed.entry(size).or_default() += 1;
+            }
+            return;
+        };
+        let pool = &mut self.pools[index];
+        pool.used += 1;
+        pool.peak = pool.peak.max(pool.used);
+        if index != fitting {
+            pool.spilled_in += 1;
+            self.spills += 1;
+        }
+        match site {
+            Some(site) => {
+                blocks.by_address.insert(site.address, index);
+            }
+            None => blocks.by_size.entry(size).or_default().push(index),
+        }
+    }
+
+    fn dealloc(&mut self, blocks: &mut Blocks, size: u32, site: Option<Site>) {
+        let index = match site {
+            Some(site) => blocks.by_address.remove(&site.address),
+            None => match blocks.failed.get_mut(&size) {
+                Some(failed) if *failed > 0 => {
+                    *failed -= 1;
+                    None
+                }
+                _ => blocks.by_size.get_mut(&size).and_then(Vec::pop),
+            },
+        };
+        if let Some(index) = index {
+            let pool = &mut self.pools[index];
+            pool.used = pool.used.saturating_sub(1);
+        }
+    }
+}
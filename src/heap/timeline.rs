@@ -0,0 +1,255 @@
+//! Heap occupancy timeline.
+
+use super::{apply, pool_loads, trace::Packet, TraceMap};
+use anyhow::Result;
+use drone_config::{format_size, HeapPool};
+use serde::Serialize;
+use std::{collections::BTreeSet, io::Write};
+
+const SVG_WIDTH: f64 = 960.0;
+const SVG_HEIGHT: f64 = 480.0;
+const SVG_MARGIN: f64 = 48.0;
+const SVG_LEGEND: f64 = 160.0;
+const SVG_COLORS: [&str; 8] =
+    ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+/// Live block counts after each trace event.
+#[derive(Serialize)]
+pub struct Timeline {
+    /// Block sizes present in the trace.
+    sizes: Vec<u32>,
+    /// Block sizes of the configured pools.
+    pools: Vec<u32>,
+    /// Counts after each event.
+    events: Vec<Sample>,
+}
+
+/// SVG chart geometry.
+struct Chart {
+    events: usize,
+    max: u32,
+}
+
+#[derive(Serialize)]
+struct Sample {
+    event: usize,
+    bytes: u64,
+    sizes: Vec<u32>,
+    pools: Vec<u32>,
+}
+
+impl Timeline {
+    /// Replays the trace `packets`, recording the live block counts of each
+    /// size and each of the configured `pools` after every event.
+    pub fn build(packets: &[Packet], max_size: u32, pools: &[HeapPool]) -> Result<Self> {
+        let sizes = packets
+            .iter()
+            .flat_map(|packet| packet.sizes().iter().flatten().copied().collect::<Vec<_>>())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut loads = pool_loads(pools);
+        let mut trace = TraceMap::new();
+        let mut events = Vec::with_capacity(packets.len());
+        for (index, &packet) in packets.iter().enumerate() {
+            apply(&mut trace, &mut loads, packet, max_size)?;
+            let counts = sizes
+                .iter()
+                .map(|size| trace.get(size).map_or(0, |entry| entry.cur))
+                .collect::<Vec<_>>();
+            let bytes = sizes
+                .iter()
+                .zip(&counts)
+                .map(|(&size, &count)| u64::from(size) * u64::from(count))
+                .sum();
+            events.push(Sample {
+                event: index + 1,
+                bytes,
+                sizes: counts,
+                pools: loads.iter().map(|load| load.cur).collect(),
+            });
+        }
+        Ok(Self { sizes, pools: loads.iter().map(|load| load.block).collect(), events })
+    }
+
+    /// Returns the first event reaching the maximum number of live bytes, and
+    /// the number of bytes.
+    pub fn peak(&self) -> Option<(usize, u64)> {
+        self.events
+            .iter()
+            .max_by(|a, b| a.bytes.cmp(&b.bytes).then(b.event.cmp(&a.event)))
+            .map(|sample| (sample.event, sample.bytes))
+    }
+
+    /// Writes the timeline in CSV format, one row per event.
+    pub fn write_csv(&self, w: &mut impl Write) -> Result<()> {
+        write!(w, "event,bytes")?;
+        for size in &self.sizes {
+            write!(w, ",size_{}", size)?;
+        }
+        for block in &self.pools {
+            write!(w, ",pool_{}", block)?;
+        }
+        writeln!(w)?;
+        for sample in &self.events {
+            write!(w, "{},{}", sample.event, sample.bytes)?;
+            for count in sample.sizes.iter().chain(&sample.pools) {
+                write!(w, ",{}", count)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the timeline in JSON format.
+    pub fn write_json(&self, w: &mut impl Write) -> Result<()> {
+        serde_json::to_writer(w, self)?;
+        Ok(())
+    }
+
+    /// Writes a step chart of the live block counts over the events in SVG
+    /// format. The pools are charted if configured, otherwise the block sizes.
+    /// The event with the maximum number of live bytes is marked.
+    pub fn write_svg(&self, w: &mut impl Write) -> Result<()> {
+        let series = self.series();
+        let max = series.iter().flat_map(|(_, counts)| counts).copied().max().unwrap_or(0);
+        let chart = Chart { events: self.events.len(), max: max.max(1) };
+        writeln!(
+            w,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+            SVG_WIDTH, SVG_HEIGHT
+        )?;
+        writeln!(w, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+        chart.write_axes(w)?;
+        for (index, (label, counts)) in series.iter().enumerate() {
+            chart.write_series(w, index, label, counts)?;
+        }
+        if let Some((event, bytes)) = self.peak() {
+            chart.write_peak(w, event, bytes)?;
+        }
+        writeln!(w, "</svg>")?;
+        Ok(())
+    }
+
+    fn series(&self) -> Vec<(String, Vec<u32>)> {
+        if self.pools.is_empty() {
+            self.sizes
+                .iter()
+                .enumerate()
+                .map(|(i, &size)| {
+                    (format!("size {}", format_size(size)), self.column(|sample| sample.sizes[i]))
+                })
+                .collect()
+        } else {
+            self.pools
+                .iter()
+                .enumerate()
+                .map(|(i, &block)| {
+                    (format!("pool {}", format_size(block)), self.column(|sample| sample.pools[i]))
+                })
+                .collect()
+        }
+    }
+
+    fn column(&self, f: impl Fn(&Sample) -> u32) -> Vec<u32> {
+        self.events.iter().map(f).collect()
+    }
+}
+
+impl Chart {
+    fn x(&self, event: usize) -> f64 {
+        let width = SVG_WIDTH - SVG_MARGIN * 2.0 - SVG_LEGEND;
+        SVG_MARGIN + event as f64 / self.events.max(1) as f64 * width
+    }
+
+    fn y(&self, count: u32) -> f64 {
+        let height = SVG_HEIGHT - SVG_MARGIN * 2.0;
+        SVG_MARGIN + height - f64::from(count) / f64::from(self.max) * height
+    }
+
+    fn write_axes(&self, w: &mut impl Write) -> Result<()> {
+        let (x0, x1, y0, y1) = (self.x(0), self.x(self.events), self.y(0), self.y(self.max));
+        writeln!(
+            w,
+            r#"<path d="M{:.1} {:.1} V{:.1} H{:.1}" fill="none" stroke="black"/>"#,
+            x0, y1, y0, x1
+        )?;
+        writeln!(
+            w,
+            r#"<text x="{:.1}" y="{:.1}" text-anchor="end">{}</text>"#,
+            x0 - 4.0,
+            y1 + 4.0,
+            self.max
+        )?;
+        writeln!(w, r#"<text x="{:.1}" y="{:.1}" text-anchor="end">0</text>"#, x0 - 4.0, y0 + 4.0)?;
+        writeln!(
+            w,
+            r#"<text x="{:.1}" y="{:.1}" text-anchor="end">{}</text>"#,
+            x1,
+            y0 + 16.0,
+            self.events
+        )?;
+        writeln!(
+            w,
+            r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">event</text>"#,
+            (x0 + x1) / 2.0,
+            y0 + 16.0
+        )?;
+        Ok(())
+    }
+
+    fn write_series(
+        &self,
+        w: &mut impl Write,
+        index: usize,
+        label: &str,
+        counts: &[u32],
+    ) -> Result<()> {
+        let color = SVG_COLORS[index % SVG_COLORS.len()];
+        let mut path = format!("M{:.1} {:.1}", self.x(0), self.y(0));
+        let mut prev = 0;
+        for (event, &count) in counts.iter().enumerate() {
+            if count != prev {
+                path.push_str(&format!(" H{:.1} V{:.1}", self.x(event), self.y(count)));
+                prev = count;
+            }
+        }
+        path.push_str(&format!(" H{:.1}", self.x(counts.len())));
+        writeln!(w, r#"<path d="{}" fill="none" stroke="{}"/>"#, path, color)?;
+        let legend_x = SVG_WIDTH - SVG_LEGEND;
+        let legend_y = SVG_MARGIN + index as f64 * 16.0;
+        writeln!(
+            w,
+            r#"<rect x="{:.1}" y="{:.1}" width="10" height="10" fill="{}"/>"#,
+            legend_x, legend_y, color
+        )?;
+        writeln!(
+            w,
+            r#"<text x="{:.1}" y="{:.1}">{}</text>"#,
+            legend_x + 14.0,
+            legend_y + 10.0,
+            label
+        )?;
+        Ok(())
+    }
+
+    fn write_peak(&self, w: &mut impl Write, event: usize, bytes: u64) -> Result<()> {
+        let x = self.x(event - 1);
+        writeln!(
+            w,
+            r#"<path d="M{:.1} {:.1} V{:.1}" stroke="red" stroke-dasharray="4 4"/>"#,
+            x,
+            self.y(self.max),
+            self.y(0)
+        )?;
+        writeln!(
+            w,
+            r#"<text x="{:.1}" y="{:.1}" fill="red">peak {} bytes at event {}</text>"#,
+            x + 4.0,
+            self.y(self.max) - 6.0,
+            bytes,
+            event
+        )?;
+        Ok(())
+    }
+}
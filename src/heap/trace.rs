@@ -1,7 +1,19 @@
 //! Heap trace file.
+//!
+//! The trace is a sequence of 32-bit frames shuffled with [`KEY`]. Each frame
+//! consists of a header byte followed by three payload bytes. The high nibble
+//! of the header selects the operation: `0xA` for allocation, `0xD` for
+//! deallocation, `0xB` for in-place grow, and `0xC` for in-place shrink.
+//!
+//! Version 1 frames (low nibble `1` to `3`) carry the block sizes only.
+//!
+//! Version 2 frames additionally carry the block address and the caller PC.
+//! Low nibble `5` starts a packet, `6` continues it, and `7` ends it. The
+//! payload is a sequence of big-endian 32-bit fields, zero-padded to whole
+//! frames: size, address, and caller for allocation and deallocation; old
+//! size, new size, address, and caller for grow and shrink.
 
 use std::{
-    fs::File,
     io,
     io::{BufReader, Read},
     ops::{Generator, GeneratorState},
@@ -12,7 +24,7 @@ use thiserror::Error;
 /// The key used to shuffle packet bits.
 pub const KEY: u32 = 0xC5AC_CE55;
 
-const MAX_FRAME: usize = 8;
+const MAX_FRAME: usize = 18;
 
 /// Heap trace file parser error.
 #[derive(Error, Debug)]
@@ -34,16 +46,21 @@ pub struct Parser {
 }
 
 /// Heap trace file packet.
+#[derive(Clone, Copy, Debug)]
 pub enum Packet {
     /// Allocate a block of memory.
     Alloc {
         /// Block size.
         size: u32,
+        /// Block location, if traced.
+        site: Option<Site>,
     },
     /// Deallocate a block of memory.
     Dealloc {
         /// Block size.
         size: u32,
+        /// Block location, if traced.
+        site: Option<Site>,
     },
     /// Extend a memory block.
     Grow {
@@ -51,6 +68,8 @@ pub enum Packet {
         old_size: u32,
         /// New block size.
         new_size: u32,
+        /// Block location, if traced.
+        site: Option<Site>,
     },
     /// Shrink a memory block.
     Shrink {
@@ -58,9 +77,32 @@ pub enum Packet {
         old_size: u32,
         /// New block size.
         new_size: u32,
+        /// Block location, if traced.
+        site: Option<Site>,
     },
 }
 
+/// Block location carried by version 2 packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Site {
+    /// Block address.
+    pub address: u32,
+    /// Program counter of the caller.
+    pub caller: u32,
+}
+
+impl Packet {
+    /// Returns the block sizes affected by the packet.
+    pub fn sizes(self) -> [Option<u32>; 2] {
+        match self {
+            Self::Alloc { size, .. } | Self::Dealloc { size, .. } => [Some(size), None],
+            Self::Grow { old_size, new_size, .. } | Self::Shrink { old_size, new_size, .. } => {
+                [Some(old_size), Some(new_size)]
+            }
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 struct Frame {
     buf: [u8; MAX_FRAME],
@@ -70,7 +112,10 @@ struct Frame {
 
 impl Parser {
     /// Create a new [`Parser`] from file.
-    pub fn new(trace_file: File) -> Result<Self, Error> {
+    ///
+    /// The trace can be read from any source, including a live stream, in
+    /// which case the packets are yielded as soon as they arrive.
+    pub fn new<R: Read + 'static>(trace_file: R) -> Result<Self, Error> {
         let reader = BufReader::new(trace_file);
         let gen = Box::pin(parser(reader));
         Ok(Self { gen })
@@ -122,6 +167,23 @@ impl Frame {
     fn is_empty(&self) -> bool {
         self.head == self.tail
     }
+
+    fn is_padding(&self) -> bool {
+        self.tail - self.head < 3 && self.buf[self.head..self.tail].iter().all(|&byte| byte == 0)
+    }
+
+    fn pop_site(&mut self) -> Result<Site, Error> {
+        Ok(Site { address: self.pop_u32()?, caller: self.pop_u32()? })
+    }
+}
+
+fn extended_index(header: u8) -> usize {
+    match header >> 4 {
+        0xA => 0,
+        0xD => 1,
+        0xB => 2,
+        _ => 3,
+    }
 }
 
 #[allow(clippy::too_many_lines)]
@@ -133,6 +195,7 @@ fn parser<R: Read>(
     let mut dealloc = Vec::new();
     let mut grow_in_place = Vec::new();
     let mut shrink_in_place = Vec::new();
+    let mut extended: [Vec<Frame>; 4] = Default::default();
     static move || {
         loop {
             reader.read_exact(&mut frame)?;
@@ -184,7 +247,7 @@ fn parser<R: Read>(
                         break Err(Error::InvalidSequence);
                     }
                     log::debug!("Alloc: 0x{:08X}", size);
-                    yield Packet::Alloc { size };
+                    yield Packet::Alloc { size, site: None };
                 }
                 0xD2 => {
                     let mut frame = dealloc.pop().ok_or(Error::InvalidSequence)?;
@@ -197,7 +260,7 @@ fn parser<R: Read>(
                         break Err(Error::InvalidSequence);
                     }
                     log::debug!("Dealloc: 0x{:08X}", size);
-                    yield Packet::Dealloc { size };
+                    yield Packet::Dealloc { size, site: None };
                 }
                 0xB3 => {
                     let mut frame = grow_in_place.pop().ok_or(Error::InvalidSequence)?;
@@ -211,7 +274,7 @@ fn parser<R: Read>(
                         break Err(Error::InvalidSequence);
                     }
                     log::debug!("Grow: 0x{:08X} -> 0x{:08X}", old_size, new_size);
-                    yield Packet::Grow { old_size, new_size };
+                    yield Packet::Grow { old_size, new_size, site: None };
                 }
                 0xC3 => {
                     let mut frame = shrink_in_place.pop().ok_or(Error::InvalidSequence)?;
@@ -225,7 +288,49 @@ fn parser<R: Read>(
                         break Err(Error::InvalidSequence);
                     }
                     log::debug!("Shrink: 0x{:08X} -> 0x{:08X}", old_size, new_size);
-                    yield Packet::Shrink { old_size, new_size };
+                    yield Packet::Shrink { old_size, new_size, site: None };
+                }
+                0xA5 | 0xD5 | 0xB5 | 0xC5 => {
+                    let mut frame = Frame::default();
+                    frame.push(payload)?;
+                    extended[extended_index(header)].push(frame);
+                }
+                0xA6 | 0xD6 | 0xB6 | 0xC6 => {
+                    extended[extended_index(header)]
+                        .last_mut()
+                        .ok_or(Error::InvalidSequence)?
+                        .push(payload)?;
+                }
+                0xA7 | 0xD7 | 0xB7 | 0xC7 => {
+                    let mut frame =
+                        extended[extended_index(header)].pop().ok_or(Error::InvalidSequence)?;
+                    frame.push(payload)?;
+                    let packet = match header {
+                        0xA7 | 0xD7 => {
+                            let size = frame.pop_u32()?;
+                            let site = Some(frame.pop_site()?);
+                            if header == 0xA7 {
+                                Packet::Alloc { size, site }
+                            } else {
+                                Packet::Dealloc { size, site }
+                            }
+                        }
+                        _ => {
+                            let old_size = frame.pop_u32()?;
+                            let new_size = frame.pop_u32()?;
+                            let site = Some(frame.pop_site()?);
+                            if header == 0xB7 {
+                                Packet::Grow { old_size, new_size, site }
+                            } else {
+                                Packet::Shrink { old_size, new_size, site }
+                            }
+                        }
+                    };
+                    if !frame.is_padding() {
+                        break Err(Error::InvalidSequence);
+                    }
+                    log::debug!("{:?}", packet);
+                    yield packet;
                 }
                 _ => break Err(Error::InvalidFrame),
             }
@@ -54,7 +54,10 @@ pub mod devices;
 pub mod heap;
 pub mod log;
 pub mod probe;
+pub mod profile;
+pub mod serial;
 pub mod templates;
+pub mod test;
 pub mod utils;
 
 use self::cli::{Cli, Cmd};
@@ -82,10 +85,12 @@ impl Cli {
             Cmd::Flash(cmd) => cmd::flash(cmd),
             Cmd::Gdb(cmd) => cmd::gdb(cmd),
             Cmd::Heap(cmd) => cmd::heap(cmd, color),
-            Cmd::Log(cmd) => cmd::log(cmd, color),
+            Cmd::Log(cmd) => cmd::log(*cmd, color),
             Cmd::New(cmd) => cmd::new(cmd, color),
+            Cmd::Profile(cmd) => cmd::profile(cmd, color),
             Cmd::Reset(cmd) => cmd::reset(cmd),
             Cmd::Support => cmd::support(color),
+            Cmd::Test(cmd) => cmd::test(cmd, color),
         }
     }
 }
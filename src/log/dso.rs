@@ -1,16 +1,30 @@
 //! Drone Serial Output protocol.
 
-use super::{Output, OutputMap};
-use anyhow::Result;
-use std::{ops::Generator, pin::Pin};
+use super::{Options, Output, OutputMap};
+use std::{
+    fs::OpenOptions,
+    io::{prelude::*, ErrorKind},
+    ops::Generator,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    thread,
+};
 
 const KEY: u8 = 0b100_1011;
 
+/// Maximum payload length of a single packet.
+pub const MAX_PAYLOAD: usize = 16;
+
 /// Creates a new DSO parser.
+///
+/// Bytes outside of packets are dropped until the next key byte.
 pub fn parser(
     outputs: &[Output],
-) -> Pin<Box<dyn Generator<u8, Yield = (), Return = Result<!>> + '_>> {
-    let outputs = OutputMap::from(outputs);
+    options: Options,
+) -> Pin<Box<dyn Generator<u8, Yield = (), Return = !> + '_>> {
+    let outputs = OutputMap::new(outputs, &options);
+    let health = Arc::clone(&options.health);
     let mut payload = Vec::with_capacity(16);
     Box::pin(static move |mut byte| {
         loop {
@@ -28,10 +42,50 @@ pub fn parser(
                     payload,
                     String::from_utf8_lossy(&payload)
                 );
-                outputs.write(port, &payload)?;
+                outputs.write(port, &payload);
+                health.packet();
                 payload.clear();
+            } else {
+                log::debug!("Dropped byte 0x{:02X} outside of packet", byte);
+                health.dropped(1);
             }
             byte = yield;
         }
     })
 }
+
+/// Encodes `data` into DSO packets for `port`.
+pub fn encode(port: u8, data: &[u8]) -> Vec<u8> {
+    let mut packets = Vec::with_capacity(data.len() + data.len() / MAX_PAYLOAD * 2 + 2);
+    for chunk in data.chunks(MAX_PAYLOAD) {
+        packets.push(KEY << 1 | port >> 4);
+        #[allow(clippy::cast_possible_truncation)]
+        packets.push(port << 4 | (chunk.len() - 1) as u8);
+        packets.extend_from_slice(chunk);
+    }
+    packets
+}
+
+/// Runs a thread forwarding `input` to the target `port` through the serial
+/// `endpoint`.
+pub fn forward(mut input: impl Read + Send + 'static, endpoint: PathBuf, port: u8) {
+    thread::spawn(move || {
+        if let Err(err) = (|| -> std::io::Result<()> {
+            let mut endpoint = OpenOptions::new().write(true).open(endpoint)?;
+            let mut buf = [0; 256];
+            loop {
+                let count = match input.read(&mut buf) {
+                    Ok(0) => break Ok(()),
+                    Ok(count) => count,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => break Err(err),
+                };
+                log::debug!("Port {} input {:?}", port, &buf[..count]);
+                endpoint.write_all(&encode(port, &buf[..count]))?;
+                endpoint.flush()?;
+            }
+        })() {
+            log::error!("Log input forwarding failed: {}", err);
+        }
+    });
+}
@@ -0,0 +1,119 @@
+//! ARM® Data Watchpoint and Trace hardware packets.
+
+/// Decoded DWT hardware packet.
+#[derive(Clone, Copy, Debug)]
+pub enum Packet {
+    /// One or more of the DWT event counters wrapped.
+    EventCounter {
+        /// CPI counter wrapped.
+        cpi: bool,
+        /// Exception overhead counter wrapped.
+        exc: bool,
+        /// Sleep counter wrapped.
+        sleep: bool,
+        /// Load-store unit counter wrapped.
+        lsu: bool,
+        /// Folded instruction counter wrapped.
+        fold: bool,
+        /// Cycle counter wrapped.
+        cyc: bool,
+    },
+    /// Exception entry, exit, or return.
+    Exception {
+        /// Exception number.
+        number: u16,
+        /// What happened to the exception.
+        action: ExceptionAction,
+    },
+    /// Periodic PC sample.
+    PcSample {
+        /// Sampled program counter, or `None` if the core was sleeping.
+        pc: Option<u32>,
+    },
+    /// Data trace PC value.
+    DataTracePc {
+        /// Comparator number.
+        comparator: u8,
+        /// Program counter of the instruction that triggered the comparator.
+        pc: u32,
+    },
+    /// Data trace address offset.
+    DataTraceAddress {
+        /// Comparator number.
+        comparator: u8,
+        /// Lower half-word of the data address.
+        offset: u16,
+    },
+    /// Data trace data value.
+    DataTraceValue {
+        /// Comparator number.
+        comparator: u8,
+        /// Whether the access was a write.
+        write: bool,
+        /// Accessed value.
+        value: u32,
+    },
+}
+
+/// Exception trace action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionAction {
+    /// The exception was entered.
+    Entered,
+    /// The exception was exited.
+    Exited,
+    /// The exception was returned to.
+    Returned,
+}
+
+/// Decoded DWT packet with the target timestamp of the local timestamp packet
+/// which followed it.
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    /// Target timestamp in core clock cycles.
+    pub cycles: u64,
+    /// Decoded packet.
+    pub packet: Packet,
+}
+
+impl Packet {
+    /// Decodes a hardware source packet with the discriminator `id`.
+    pub fn decode(id: u8, payload: &[u8]) -> Option<Self> {
+        let value = payload
+            .iter()
+            .enumerate()
+            .fold(0, |value, (i, &byte)| value | u32::from(byte) << (8 * i));
+        match (id, payload.len()) {
+            (0, 1) => Some(Self::EventCounter {
+                cpi: value & 0b00_0001 != 0,
+                exc: value & 0b00_0010 != 0,
+                sleep: value & 0b00_0100 != 0,
+                lsu: value & 0b00_1000 != 0,
+                fold: value & 0b01_0000 != 0,
+                cyc: value & 0b10_0000 != 0,
+            }),
+            (1, 2) => {
+                let number = (value & 0x1FF) as u16;
+                let action = match value >> 12 & 0b11 {
+                    0b01 => ExceptionAction::Entered,
+                    0b10 => ExceptionAction::Exited,
+                    0b11 => ExceptionAction::Returned,
+                    _ => return None,
+                };
+                Some(Self::Exception { number, action })
+            }
+            (2, 4) => Some(Self::PcSample { pc: Some(value) }),
+            (2, 1) if value == 0 => Some(Self::PcSample { pc: None }),
+            (8..=15, 4) if id & 1 == 0 => {
+                Some(Self::DataTracePc { comparator: id >> 1 & 0b11, pc: value })
+            }
+            (8..=15, 2) if id & 1 == 1 => {
+                Some(Self::DataTraceAddress { comparator: id >> 1 & 0b11, offset: value as u16 })
+            }
+            (16..=23, _) => {
+                Some(Self::DataTraceValue { comparator: id >> 1 & 0b11, write: id & 1 == 1, value })
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Log link health statistics.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of the log link health, shared between the capture thread and the
+/// command.
+#[derive(Debug, Default)]
+pub struct Health {
+    bytes: AtomicU64,
+    packets: AtomicU64,
+    bad_packets: AtomicU64,
+    dropped_bytes: AtomicU64,
+    overflows: AtomicU64,
+    resyncs: AtomicU64,
+    output_errors: AtomicU64,
+}
+
+/// Snapshot of the [`Health`] counters.
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    /// Number of received bytes.
+    pub bytes: u64,
+    /// Number of successfully decoded packets.
+    pub packets: u64,
+    /// Number of malformed packets.
+    pub bad_packets: u64,
+    /// Number of bytes skipped while out of sync.
+    pub dropped_bytes: u64,
+    /// Number of ITM overflow packets.
+    pub overflows: u64,
+    /// Number of times the stream was resynchronized.
+    pub resyncs: u64,
+    /// Number of failed writes to the outputs.
+    pub output_errors: u64,
+}
+
+impl Health {
+    /// Counts received bytes.
+    pub fn bytes(&self, count: usize) {
+        self.bytes.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a successfully decoded packet.
+    pub fn packet(&self) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a malformed packet.
+    pub fn bad_packet(&self) {
+        self.bad_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts bytes skipped while out of sync.
+    pub fn dropped(&self, count: usize) {
+        self.dropped_bytes.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts an ITM overflow packet.
+    pub fn overflow(&self) {
+        self.overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a resynchronization.
+    pub fn resync(&self) {
+        self.resyncs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a failed output write.
+    pub fn output_error(&self) {
+        self.output_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the counters.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            packets: self.packets.load(Ordering::Relaxed),
+            bad_packets: self.bad_packets.load(Ordering::Relaxed),
+            dropped_bytes: self.dropped_bytes.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+            resyncs: self.resyncs.load(Ordering::Relaxed),
+            output_errors: self.output_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Summary {
+    /// Returns `true` if no problems were detected.
+    pub fn is_healthy(&self) -> bool {
+        self.bad_packets == 0
+            && self.dropped_bytes == 0
+            && self.overflows == 0
+            && self.output_errors == 0
+    }
+}
@@ -0,0 +1,200 @@
+//! Deferred-format logging with interned strings.
+//!
+//! Format strings are placed by the firmware into the `.drone_strings` ELF
+//! section as NUL-terminated strings. Instead of a formatted message, the
+//! firmware writes to the designated port a frame consisting of a 16-bit
+//! little-endian offset of the format string within the section, followed by
+//! the arguments in little-endian encoding.
+//!
+//! A format string refers to its arguments with `{=TYPE}` or `{=TYPE:x}`
+//! placeholders, where `TYPE` is one of `u8`, `u16`, `u32`, `u64`, `i8`,
+//! `i16`, `i32`, `i64`, `f32`, `bool`, or `str`. A `str` argument is encoded
+//! as a one-byte length followed by the UTF-8 bytes. `{{` and `}}` stand for
+//! literal braces.
+
+use crate::utils::search_rust_tool;
+use anyhow::{bail, Result};
+use std::{
+    cell::RefCell, convert::TryInto, fmt::Write, fs, path::Path, process::Command, sync::Arc,
+};
+use tempfile::tempdir;
+use thiserror::Error;
+
+/// ELF section containing the format strings.
+pub const SECTION: &str = ".drone_strings";
+
+const KINDS: &[&str] =
+    &["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "bool", "str"];
+
+/// Format strings table read from the firmware.
+#[derive(Debug)]
+pub struct Strings {
+    port: u8,
+    table: Vec<u8>,
+}
+
+/// Streaming decoder of the interned port.
+pub struct Decoder {
+    strings: Arc<Strings>,
+    buf: RefCell<Vec<u8>>,
+}
+
+/// Interned frame decoding error.
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    /// Offset doesn't point to a format string.
+    #[error("invalid format string offset 0x{0:04X}")]
+    Offset(u16),
+    /// Format string is malformed.
+    #[error("invalid format string {0:?}")]
+    Format(String),
+}
+
+impl Strings {
+    /// Reads the format strings table from the `firmware` ELF file with
+    /// `llvm-objcopy`. Frames are expected on `port`.
+    pub fn read(firmware: &Path, port: u8) -> Result<Self> {
+        let dir = tempdir()?;
+        let table = dir.path().join("strings");
+        let mut objcopy = Command::new(search_rust_tool("llvm-objcopy")?);
+        objcopy.arg("--output-target=binary").arg(format!("--only-section={}", SECTION));
+        objcopy.arg(firmware).arg(&table);
+        let output = objcopy.output()?;
+        if !output.status.success() {
+            bail!("`{:?}` failed: {}", objcopy, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        let table = fs::read(table)?;
+        if table.is_empty() {
+            bail!("`{}` section is missing or empty in `{}`", SECTION, firmware.display());
+        }
+        Ok(Self { port, table })
+    }
+
+    /// Returns the port carrying the frames.
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    fn get(&self, offset: u16) -> Result<&str, DecodeError> {
+        let tail = self.table.get(usize::from(offset)..).ok_or(DecodeError::Offset(offset))?;
+        let end = tail.iter().position(|&b| b == 0).ok_or(DecodeError::Offset(offset))?;
+        std::str::from_utf8(&tail[..end]).map_err(|_| DecodeError::Offset(offset))
+    }
+}
+
+impl Decoder {
+    /// Creates a new decoder.
+    pub fn new(strings: Arc<Strings>) -> Self {
+        Self { strings, buf: RefCell::new(Vec::new()) }
+    }
+
+    /// Returns the port carrying the frames.
+    pub fn port(&self) -> u8 {
+        self.strings.port
+    }
+
+    /// Feeds `data` received on the interned port, returning the completed
+    /// messages. On error the buffered data is discarded.
+    pub fn feed(&self, data: &[u8]) -> Result<Vec<String>, DecodeError> {
+        let mut buf = self.buf.borrow_mut();
+        buf.extend_from_slice(data);
+        let mut messages = Vec::new();
+        loop {
+            match decode(&self.strings, &buf) {
+                Ok(Some((message, length))) => {
+                    messages.push(message);
+                    buf.drain(..length);
+                }
+                Ok(None) => break Ok(messages),
+                Err(err) => {
+                    buf.clear();
+                    break Err(err);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes a single frame from the beginning of `buf`. Returns the message
+/// and the frame length, or `None` if the frame is incomplete.
+fn decode(strings: &Strings, buf: &[u8]) -> Result<Option<(String, usize)>, DecodeError> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let format = strings.get(u16::from_le_bytes([buf[0], buf[1]]))?;
+    let mut position = 2;
+    let mut message = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                message.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                message.push('}');
+            }
+            '{' => {
+                let spec = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+                let spec = spec.trim_start_matches('=');
+                let (kind, hex) = match spec.find(':') {
+                    Some(i) if &spec[i + 1..] == "x" => (&spec[..i], true),
+                    Some(_) => return Err(DecodeError::Format(format.into())),
+                    None => (spec, false),
+                };
+                if !KINDS.contains(&kind) {
+                    return Err(DecodeError::Format(format.into()));
+                }
+                match argument(kind, hex, &buf[position..], &mut message) {
+                    Some(length) => position += length,
+                    None => return Ok(None),
+                }
+            }
+            _ => message.push(c),
+        }
+    }
+    Ok(Some((message, position)))
+}
+
+/// Formats a single argument of `kind` from `data` into `message`. Returns
+/// the number of consumed bytes, or `None` if `data` is too short.
+fn argument(kind: &str, hex: bool, data: &[u8], message: &mut String) -> Option<usize> {
+    macro_rules! int {
+        ($ty:ty) => {{
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            let value = <$ty>::from_le_bytes(data.get(..SIZE)?.try_into().unwrap());
+            if hex {
+                write!(message, "{:#x}", value).unwrap();
+            } else {
+                write!(message, "{}", value).unwrap();
+            }
+            SIZE
+        }};
+    }
+    Some(match kind {
+        "u8" => int!(u8),
+        "u16" => int!(u16),
+        "u32" => int!(u32),
+        "u64" => int!(u64),
+        "i8" => int!(i8),
+        "i16" => int!(i16),
+        "i32" => int!(i32),
+        "i64" => int!(i64),
+        "f32" => {
+            let value = f32::from_le_bytes(data.get(..4)?.try_into().unwrap());
+            write!(message, "{}", value).unwrap();
+            4
+        }
+        "bool" => {
+            write!(message, "{}", *data.first()? != 0).unwrap();
+            1
+        }
+        "str" => {
+            let length = usize::from(*data.first()?);
+            message.push_str(&String::from_utf8_lossy(data.get(1..=length)?));
+            1 + length
+        }
+        _ => unreachable!(),
+    })
+}
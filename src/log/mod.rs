@@ -1,40 +1,172 @@
 //! Debug log interface.
 
 pub mod dso;
+pub mod dwt;
+pub mod health;
+pub mod interned;
+pub mod rtt;
+pub mod semihosting;
 pub mod swo;
+pub mod timeline;
+pub mod watch;
 
 mod output;
 
-pub use self::output::{Output, OutputMap, OutputStream};
+pub use self::output::{Output, OutputMap, OutputStream, PORTS_COUNT};
 
-use anyhow::Result;
+use self::{health::Health, watch::Watch};
+use crate::{
+    cli::{LogFileOpts, LogFormat, LogOutput},
+    color::Color,
+};
+use anyhow::{bail, Result};
+use serde::Serialize;
 use std::{
+    collections::BTreeMap,
     fs::File,
-    io::prelude::*,
+    io::{prelude::*, ErrorKind},
     ops::{Generator, GeneratorState},
-    path::PathBuf,
+    path::{Path, PathBuf},
     pin::Pin,
+    sync::{mpsc::Sender, Arc},
     thread,
 };
 
-type ParserFn = fn(&[Output]) -> Pin<Box<dyn Generator<u8, Yield = (), Return = Result<!>> + '_>>;
+/// Number of sources which can be captured in one session.
+pub const SOURCES_COUNT: usize = 2;
+
+type ParserFn = fn(&[Output], Options) -> Pin<Box<dyn Generator<u8, Yield = (), Return = !> + '_>>;
+
+/// Log capture options.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Prefix each line with the target timestamp.
+    pub timestamps: bool,
+    /// Core clock frequency in Hz, used to convert timestamps to microseconds.
+    pub core_clock: Option<u32>,
+    /// Prefix each standard output line with the port label.
+    pub labels: bool,
+    /// Names of the ports declared in the `[log.ports]` table.
+    pub port_names: BTreeMap<u32, String>,
+    /// Output format.
+    pub format: LogFormat,
+    /// Output file mode and rotation.
+    pub files: LogFileOpts,
+    /// Color preference for the port labels.
+    pub color: Color,
+    /// Enable periodic PC sampling.
+    pub pc_sampling: bool,
+    /// Enable exception trace.
+    pub exception_trace: bool,
+    /// Receiver of decoded DWT hardware packets.
+    pub dwt_events: Option<Sender<dwt::Event>>,
+    /// Receiver of complete lines written to the port.
+    pub port_lines: Option<(u8, Sender<String>)>,
+    /// Receiver of raw data written to the port.
+    pub port_data: Option<(u8, Sender<Vec<u8>>)>,
+    /// File to record the raw byte stream to.
+    pub record: Option<PathBuf>,
+    /// Link health counters.
+    pub health: Arc<Health>,
+    /// Interned format strings for deferred-format logging.
+    pub interned: Option<Arc<interned::Strings>>,
+    /// Conditions to stop logging on.
+    pub watch: Option<Arc<Watch>>,
+    /// Source of the captured input, set when several inputs are captured in
+    /// one session.
+    pub source: Option<Source>,
+}
+
+/// Source of a captured input.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// ARM® SWO.
+    Swo,
+    /// Drone Serial Output.
+    Dso,
+}
+
+impl Source {
+    /// Returns the source name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Swo => "swo",
+            Self::Dso => "dso",
+        }
+    }
+
+    /// Returns the index of the line assembling buffer for `port` of
+    /// `source`. Lines from different sources are assembled separately.
+    pub fn line_index(source: Option<Self>, port: u8) -> usize {
+        source.map_or(0, |source| source as usize) * PORTS_COUNT + port as usize
+    }
+}
+
+/// Resolves the port names of `outputs` with the `[log.ports]` table.
+pub fn resolve_ports(outputs: &mut [LogOutput], ports: &BTreeMap<String, u32>) -> Result<()> {
+    for output in outputs {
+        for name in output.names.drain(..) {
+            match ports.get(&name) {
+                Some(&port) if (port as usize) < PORTS_COUNT => output.ports.push(port),
+                Some(port) => bail!("Port `{}` = {} is out of range", name, port),
+                None => bail!("Port `{}` is not declared in `[log.ports]`", name),
+            }
+        }
+    }
+    Ok(())
+}
 
 /// Runs log capture thread.
-pub fn capture(input: PathBuf, outputs: Vec<Output>, parser: ParserFn) {
+pub fn capture(input: PathBuf, outputs: Arc<[Output]>, options: Options, parser: ParserFn) {
     thread::spawn(move || {
-        (|| -> Result<()> {
-            let input = File::open(input)?;
-            let mut parser = Box::pin(parser(&outputs));
-            for byte in input.bytes() {
-                let byte = byte?;
-                log::debug!("BYTE 0b{0:08b} 0x{0:02X} {1:?}", byte, char::from(byte));
-                match parser.as_mut().resume(byte) {
-                    GeneratorState::Yielded(()) => (),
-                    GeneratorState::Complete(Err(err)) => panic!("log parser failure: {}", err),
-                }
-            }
-            Ok(())
-        })()
-        .expect("log capture thread failed");
+        if let Err(err) = (|| -> Result<()> {
+            let record = options.record.as_ref().map(File::create).transpose()?;
+            parse(File::open(input)?, record, &outputs, options, parser)
+        })() {
+            log::error!("Log capture failed: {}", err);
+        }
     });
 }
+
+/// Decodes a previously recorded raw byte stream from the `input` file.
+pub fn replay(input: &Path, outputs: &[Output], options: Options, parser: ParserFn) -> Result<()> {
+    parse(File::open(input)?, None, outputs, options, parser)?;
+    Output::flush_all(outputs);
+    Ok(())
+}
+
+fn parse(
+    mut input: File,
+    mut record: Option<File>,
+    outputs: &[Output],
+    options: Options,
+    parser: ParserFn,
+) -> Result<()> {
+    let health = Arc::clone(&options.health);
+    let watch = options.watch.clone();
+    let mut parser = Box::pin(parser(outputs, options));
+    let mut buf = [0; 256];
+    loop {
+        if watch.as_ref().map_or(false, |watch| watch.outcome().is_some()) {
+            break Ok(());
+        }
+        let count = match input.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(count) => count,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => break Err(err.into()),
+        };
+        health.bytes(count);
+        if let Some(record) = &mut record {
+            record.write_all(&buf[..count])?;
+        }
+        for &byte in &buf[..count] {
+            log::debug!("BYTE 0b{0:08b} 0x{0:02X} {1:?}", byte, char::from(byte));
+            match parser.as_mut().resume(byte) {
+                GeneratorState::Yielded(()) => (),
+            }
+        }
+    }
+}
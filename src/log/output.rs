@@ -1,20 +1,87 @@
-use crate::cli;
+use super::{health::Health, interned::Decoder, watch::Watch, Options, Source, SOURCES_COUNT};
+use crate::cli::{LogFileMode, LogFileOpts, LogFormat, LogOutput, LogSink};
+use ansi_term::Colour;
+use serde::Serialize;
 use std::{
-    cell::RefCell,
-    fs::{File, OpenOptions},
+    cell::{Cell, RefCell},
+    fs::{remove_file, rename, symlink_metadata, File, OpenOptions},
     io,
     io::{prelude::*, stdout, Stdout},
+    net::TcpListener,
+    os::unix::{fs::FileTypeExt, net::UnixListener},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Number of writes queued for a socket client before it's dropped as too
+/// slow.
+const CLIENT_QUEUE_LEN: usize = 1024;
+
 /// Number of ports.
 pub const PORTS_COUNT: usize = 32;
 
+const PORT_COLOURS: [Colour; 6] =
+    [Colour::Green, Colour::Yellow, Colour::Blue, Colour::Purple, Colour::Cyan, Colour::Red];
+
 /// Opened output.
+///
+/// Outputs can be shared between threads capturing different sources.
 pub struct Output {
     /// Selected ports.
     ports: Vec<u32>,
     /// Output stream.
-    stream: RefCell<OutputStream>,
+    stream: Mutex<OutputStream>,
+    /// Whether the next byte of each port and source starts a new line.
+    line_starts: Vec<AtomicBool>,
+    /// Line assembling state, if the output is line-framed.
+    framing: Option<Framing>,
+}
+
+struct Framing {
+    lines: Mutex<Vec<Line>>,
+    labels: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    json: bool,
+}
+
+/// Line being assembled.
+#[derive(Clone, Default)]
+struct Line {
+    bytes: Vec<u8>,
+    source: Option<Source>,
+    timestamp: Option<Timestamp>,
+}
+
+/// Target timestamp of the data being written.
+#[derive(Clone, Copy)]
+struct Timestamp {
+    cycles: u64,
+    core_clock: Option<u32>,
+}
+
+/// JSON-lines output record.
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<Source>,
+    port: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<String>,
+    host_time: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_cycles: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_micros: Option<f64>,
 }
 
 /// Output stream.
@@ -22,61 +89,406 @@ pub enum OutputStream {
     /// Standard output.
     Stdout(Stdout),
     /// File output.
-    File(File),
+    File(LogFile),
+    /// Socket output fanned out to every connected client.
+    Socket(Subscribers),
+    /// Output discarding the data.
+    Discard,
+}
+
+/// Output file with optional rotation.
+pub struct LogFile {
+    file: File,
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    size: u64,
+    opened: Instant,
+}
+
+struct Rotation {
+    size: Option<u64>,
+    interval: Option<Duration>,
+    keep: u32,
 }
 
+/// Connected socket clients.
+///
+/// Each client is written by its own thread from a bounded queue, so that a
+/// slow client doesn't block the capture. A client whose queue is full is
+/// dropped.
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<Mutex<Vec<SyncSender<Vec<u8>>>>>);
+
 /// Output map.
-pub struct OutputMap<'a>([Vec<&'a RefCell<OutputStream>>; PORTS_COUNT]);
+pub struct OutputMap<'a> {
+    map: [Vec<&'a Output>; PORTS_COUNT],
+    timestamp: Option<Cell<u64>>,
+    core_clock: Option<u32>,
+    health: Arc<Health>,
+    interned: Option<Decoder>,
+    watch: Option<Arc<Watch>>,
+    port_lines: Option<PortLines>,
+    port_data: Option<(u8, Sender<Vec<u8>>)>,
+    source: Option<Source>,
+}
+
+struct PortLines {
+    port: u8,
+    lines: Sender<String>,
+    line: RefCell<Vec<u8>>,
+}
 
 impl Output {
     /// Opens all output streams.
-    pub fn open_all(outputs: &[cli::LogOutput]) -> io::Result<Vec<Output>> {
-        outputs
-            .iter()
-            .map(|cli::LogOutput { ports, path }| {
-                if path.is_empty() {
-                    Ok(OutputStream::Stdout(stdout()))
-                } else {
-                    OpenOptions::new().write(true).open(path).map(OutputStream::File)
-                }
-                .map(|stream| Self { ports: ports.clone(), stream: RefCell::new(stream) })
+    ///
+    /// All standard output sinks are merged into one output. Standard output
+    /// selecting more than one port is line-framed, so that lines from
+    /// different ports don't interleave. In `jsonl` format the standard output
+    /// and sockets are line-framed, each line becoming a JSON record, while
+    /// files keep the raw port data. When several sources are captured, the
+    /// standard output and sockets are line-framed and each line is tagged with
+    /// its source. Files are never line-framed, as they may carry binary
+    /// streams.
+    pub fn open_all(outputs: &[LogOutput], options: &Options) -> io::Result<Arc<[Output]>> {
+        merge_stdout(outputs)
+            .into_iter()
+            .map(|(ports, sink)| {
+                let stream = match sink {
+                    LogSink::Stdout => OutputStream::Stdout(stdout()),
+                    LogSink::File(path) => OutputStream::File(LogFile::open(path, &options.files)?),
+                    LogSink::Tcp(address) => OutputStream::Socket(Subscribers::tcp(address)?),
+                    LogSink::Unix(path) => OutputStream::Socket(Subscribers::unix(path)?),
+                    LogSink::Discard => OutputStream::Discard,
+                };
+                let stdout = matches!(sink, LogSink::Stdout);
+                let text = matches!(sink, LogSink::Stdout | LogSink::Tcp(_) | LogSink::Unix(_));
+                let json = text && matches!(options.format, LogFormat::Jsonl);
+                let tagged = text && options.source.is_some();
+                let framing = (json || tagged || stdout && (ports.len() != 1 || options.labels))
+                    .then(|| Framing {
+                        lines: Mutex::new(vec![Line::default(); PORTS_COUNT * SOURCES_COUNT]),
+                        labels: (options.labels && stdout && !json).then(|| labels(options)),
+                        tags: (tagged && !json).then(|| tags(options, stdout)),
+                        json,
+                    });
+                Ok(Self {
+                    ports,
+                    stream: Mutex::new(stream),
+                    line_starts: (0..PORTS_COUNT * SOURCES_COUNT)
+                        .map(|_| AtomicBool::new(true))
+                        .collect(),
+                    framing,
+                })
             })
-            .collect()
+            .collect::<io::Result<Vec<_>>>()
+            .map(Into::into)
+    }
+
+    /// Writes out the pending partial lines of all outputs. Called at the end
+    /// of a session.
+    pub fn flush_all(outputs: &[Self]) {
+        for output in outputs {
+            if let Err(err) = output.flush() {
+                log::warn!("Output flush failed: {}", err);
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        source: Option<Source>,
+        port: u8,
+        data: &[u8],
+        timestamp: Option<Timestamp>,
+    ) -> io::Result<()> {
+        if let Some(framing) = &self.framing {
+            return self.write_lines(framing, source, port, data, timestamp);
+        }
+        let mut stream = self.stream.lock().unwrap();
+        let prefix = timestamp.map(Timestamp::prefix);
+        let prefix = if let Some(prefix) = &prefix {
+            prefix
+        } else {
+            return stream.write(data);
+        };
+        let line_start = &self.line_starts[Source::line_index(source, port)];
+        let mut data = data;
+        while !data.is_empty() {
+            if line_start.swap(false, Ordering::Relaxed) {
+                stream.write(prefix.as_bytes())?;
+            }
+            let end = data.iter().position(|&b| b == b'\n').map_or(data.len(), |i| {
+                line_start.store(true, Ordering::Relaxed);
+                i + 1
+            });
+            stream.write(&data[..end])?;
+            data = &data[end..];
+        }
+        Ok(())
+    }
+
+    fn write_lines(
+        &self,
+        framing: &Framing,
+        source: Option<Source>,
+        port: u8,
+        data: &[u8],
+        timestamp: Option<Timestamp>,
+    ) -> io::Result<()> {
+        let mut lines = framing.lines.lock().unwrap();
+        let line = &mut lines[Source::line_index(source, port)];
+        for &byte in data {
+            if line.bytes.is_empty() {
+                line.source = source;
+                line.timestamp = timestamp;
+            }
+            line.bytes.push(byte);
+            if byte == b'\n' {
+                self.write_line(framing, port, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_line(&self, framing: &Framing, port: u8, line: &mut Line) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap();
+        if framing.json {
+            stream.write(&json_record(line.source, port, &line.bytes, line.timestamp))?;
+        } else {
+            if let Some(prefix) = line.timestamp.map(Timestamp::prefix) {
+                stream.write(prefix.as_bytes())?;
+            }
+            if let (Some(tags), Some(source)) = (&framing.tags, line.source) {
+                stream.write(tags[source as usize].as_bytes())?;
+            }
+            if let Some(labels) = &framing.labels {
+                stream.write(labels[port as usize].as_bytes())?;
+            }
+            stream.write(&line.bytes)?;
+        }
+        line.bytes.clear();
+        Ok(())
+    }
+
+    /// Writes out the pending partial lines, terminating each with a newline.
+    fn flush(&self) -> io::Result<()> {
+        if let Some(framing) = &self.framing {
+            let mut lines = framing.lines.lock().unwrap();
+            for (index, line) in lines.iter_mut().enumerate() {
+                if !line.bytes.is_empty() {
+                    line.bytes.push(b'\n');
+                    #[allow(clippy::cast_possible_truncation)]
+                    self.write_line(framing, (index % PORTS_COUNT) as u8, line)?;
+                }
+            }
+        }
+        Ok(())
     }
 }
 
-impl<'a> From<&'a [Output]> for OutputMap<'a> {
-    fn from(outputs: &'a [Output]) -> Self {
-        let mut map: [Vec<&RefCell<OutputStream>>; PORTS_COUNT] = Default::default();
-        for Output { ports, stream } in outputs {
-            if ports.is_empty() {
+impl<'a> OutputMap<'a> {
+    /// Creates a new output map.
+    pub fn new(outputs: &'a [Output], options: &Options) -> Self {
+        let mut map: [Vec<&Output>; PORTS_COUNT] = Default::default();
+        for output in outputs {
+            if output.ports.is_empty() {
                 for outputs in &mut map {
-                    outputs.push(stream);
+                    outputs.push(output);
                 }
             } else {
-                for port in ports {
+                for port in &output.ports {
                     if let Some(map) = map.get_mut(*port as usize) {
-                        map.push(stream);
+                        map.push(output);
                     } else {
                         log::warn!("Ignoring port {}", port);
                     }
                 }
             }
         }
-        OutputMap(map)
+        let timestamp = options.timestamps.then_some(Cell::new(0));
+        Self {
+            map,
+            timestamp,
+            core_clock: options.core_clock,
+            health: Arc::clone(&options.health),
+            interned: options.interned.clone().map(Decoder::new),
+            watch: options.watch.clone(),
+            port_lines: options.port_lines.clone().map(|(port, lines)| PortLines {
+                port,
+                lines,
+                line: RefCell::new(Vec::new()),
+            }),
+            port_data: options.port_data.clone(),
+            source: options.source,
+        }
     }
 }
 
 impl OutputMap<'_> {
     /// Write `data` to all `port` outputs.
-    pub fn write(&self, port: u8, data: &[u8]) -> io::Result<()> {
-        for output in &self.0[port as usize] {
-            output.borrow_mut().write(data)?;
+    ///
+    /// Data on the interned port is decoded into messages first. A failed
+    /// output is counted as unhealthy and doesn't prevent writing to the rest.
+    /// The written lines are matched against the stop conditions.
+    pub fn write(&self, port: u8, data: &[u8]) {
+        match &self.interned {
+            Some(decoder) if decoder.port() == port => match decoder.feed(data) {
+                Ok(messages) => {
+                    for message in messages {
+                        self.write_port(port, format!("{}\n", message).as_bytes());
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Port {} interned frame: {}", port, err);
+                    self.health.bad_packet();
+                }
+            },
+            _ => self.write_port(port, data),
+        }
+    }
+
+    fn write_port(&self, port: u8, data: &[u8]) {
+        let timestamp = self
+            .timestamp
+            .as_ref()
+            .map(|timestamp| Timestamp { cycles: timestamp.get(), core_clock: self.core_clock });
+        for output in &self.map[port as usize] {
+            if let Err(err) = output.write(self.source, port, data, timestamp) {
+                log::warn!("Port {} output write failed: {}", port, err);
+                self.health.output_error();
+            }
+        }
+        if let Some(port_lines) = &self.port_lines {
+            if port_lines.port == port {
+                port_lines.feed(data);
+            }
+        }
+        if let Some((data_port, sender)) = &self.port_data {
+            if *data_port == port {
+                sender.send(data.to_vec()).ok();
+            }
+        }
+        if let Some(watch) = &self.watch {
+            watch.feed(self.source, port, data);
+        }
+    }
+
+    /// Updates the current target timestamp in core clock cycles.
+    pub fn set_timestamp(&self, cycles: u64) {
+        if let Some(timestamp) = &self.timestamp {
+            timestamp.set(cycles);
+        }
+    }
+}
+
+/// Merges all standard output sinks into one, so that they share the line
+/// framing. A merged sink without port selection receives all ports.
+fn merge_stdout(outputs: &[LogOutput]) -> Vec<(Vec<u32>, &LogSink)> {
+    let mut merged: Vec<(Vec<u32>, &LogSink)> = Vec::new();
+    for LogOutput { ports, sink, .. } in outputs {
+        let stdout = merged.iter_mut().find(|(_, sink)| matches!(sink, LogSink::Stdout));
+        match stdout {
+            Some((merged_ports, _)) if matches!(sink, LogSink::Stdout) => {
+                if merged_ports.is_empty() || ports.is_empty() {
+                    merged_ports.clear();
+                } else {
+                    merged_ports.extend(ports);
+                    merged_ports.sort_unstable();
+                    merged_ports.dedup();
+                }
+            }
+            _ => merged.push((ports.clone(), sink)),
+        }
+    }
+    merged
+}
+
+/// Returns the line labels of all ports. Named ports are labeled with their
+/// names.
+fn labels(options: &Options) -> Vec<String> {
+    (0..PORTS_COUNT)
+        .map(|port| {
+            let name = options
+                .port_names
+                .get(&(port as u32))
+                .map_or_else(|| format!("port {}", port), Clone::clone);
+            let colour = PORT_COLOURS[port % PORT_COLOURS.len()];
+            format!("{} ", options.color.bold_fg(&format!("[{}]", name), colour))
+        })
+        .collect()
+}
+
+/// Returns the line tags of all sources. Tags are colored on the standard
+/// output only.
+fn tags(options: &Options, stdout: bool) -> Vec<String> {
+    [Source::Swo, Source::Dso]
+        .iter()
+        .map(|source| {
+            let tag = format!("[{}]", source.name());
+            if stdout { format!("{} ", options.color.bold(&tag)) } else { format!("{} ", tag) }
+        })
+        .collect()
+}
+
+impl PortLines {
+    fn feed(&self, data: &[u8]) {
+        let mut line = self.line.borrow_mut();
+        for &byte in data {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).trim_end_matches('\r').to_string();
+            line.clear();
+            self.lines.send(text).ok();
         }
-        Ok(())
     }
 }
 
+impl Timestamp {
+    fn micros(self) -> Option<f64> {
+        self.core_clock.map(|core_clock| self.cycles as f64 * 1_000_000.0 / f64::from(core_clock))
+    }
+
+    fn prefix(self) -> String {
+        if let Some(micros) = self.micros() {
+            format!("[{:>12} {:>14.3}us] ", self.cycles, micros)
+        } else {
+            format!("[{:>12}] ", self.cycles)
+        }
+    }
+}
+
+/// Serializes a complete `line` received on `port` of `source` into a
+/// JSON-lines record.
+///
+/// The line terminator is stripped. A line which is not valid UTF-8 is
+/// encoded in base64 as `bytes` instead of `text`.
+fn json_record(
+    source: Option<Source>,
+    port: u8,
+    line: &[u8],
+    timestamp: Option<Timestamp>,
+) -> Vec<u8> {
+    let end = line.iter().rposition(|&b| b != b'\n' && b != b'\r').map_or(0, |i| i + 1);
+    let line = &line[..end];
+    let text = std::str::from_utf8(line).ok();
+    let record = Record {
+        source,
+        port,
+        text,
+        bytes: text.is_none().then(|| base64::encode(line)),
+        host_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |duration| duration.as_secs_f64()),
+        target_cycles: timestamp.map(|timestamp| timestamp.cycles),
+        target_micros: timestamp.and_then(Timestamp::micros),
+    };
+    let mut json = serde_json::to_vec(&record).unwrap();
+    json.push(b'\n');
+    json
+}
+
 impl OutputStream {
     /// Write `data` to the output.
     pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
@@ -87,7 +499,153 @@ impl OutputStream {
         }
         match self {
             Self::Stdout(stdout) => write_stream(stdout, data),
-            Self::File(file) => write_stream(file, data),
+            Self::File(file) => {
+                file.rotate_if_needed(data.len())?;
+                write_stream(&mut file.file, data)?;
+                file.size += data.len() as u64;
+                Ok(())
+            }
+            Self::Socket(subscribers) => {
+                let mut clients = subscribers.0.lock().unwrap();
+                clients.retain(|client| match client.try_send(data.to_vec()) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) => {
+                        log::warn!("Dropping a log client not keeping up with the output");
+                        false
+                    }
+                    Err(TrySendError::Disconnected(_)) => false,
+                });
+                Ok(())
+            }
+            Self::Discard => Ok(()),
+        }
+    }
+}
+
+impl LogFile {
+    /// Opens the output file at `path` according to `opts`.
+    pub fn open(path: &Path, opts: &LogFileOpts) -> io::Result<Self> {
+        let mut open_options = OpenOptions::new();
+        match opts.mode {
+            LogFileMode::Write => open_options.write(true),
+            LogFileMode::Create => open_options.write(true).create_new(true),
+            LogFileMode::Truncate => open_options.write(true).create(true).truncate(true),
+            LogFileMode::Append => open_options.append(true).create(true),
+        };
+        let file = open_options.open(path)?;
+        let metadata = file.metadata()?;
+        let size = if let LogFileMode::Append = opts.mode { metadata.len() } else { 0 };
+        let mut rotation =
+            (opts.rotate_size.is_some() || opts.rotate_interval.is_some()).then(|| Rotation {
+                size: opts.rotate_size.map(u64::from),
+                interval: opts.rotate_interval.map(Duration::from_secs),
+                keep: opts.rotate_keep,
+            });
+        if rotation.is_some() && !metadata.is_file() {
+            log::warn!("Not rotating `{}`: not a regular file", path.display());
+            rotation = None;
         }
+        Ok(Self { file, path: path.to_path_buf(), rotation, size, opened: Instant::now() })
+    }
+
+    /// Rotates the file if writing `len` more bytes would exceed the size
+    /// limit, or if the rotation interval has passed.
+    ///
+    /// Rotated files are renamed to `path.1`, `path.2`, and so on, the oldest
+    /// beyond the `keep` count being removed.
+    fn rotate_if_needed(&mut self, len: usize) -> io::Result<()> {
+        let rotation = match &self.rotation {
+            Some(rotation) => rotation,
+            None => return Ok(()),
+        };
+        let size_exceeded =
+            rotation.size.map_or(false, |size| self.size > 0 && self.size + len as u64 > size);
+        let interval_passed =
+            rotation.interval.map_or(false, |interval| self.opened.elapsed() >= interval);
+        if !size_exceeded && !interval_passed {
+            return Ok(());
+        }
+        let rotated = |index: u32| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", index));
+            PathBuf::from(path)
+        };
+        if rotation.keep == 0 {
+            remove_file(&self.path)?;
+        } else {
+            for index in (1..rotation.keep).rev() {
+                let from = rotated(index);
+                if from.exists() {
+                    rename(from, rotated(index + 1))?;
+                }
+            }
+            rename(&self.path, rotated(1))?;
+        }
+        log::info!("Rotated `{}`", self.path.display());
+        self.file = OpenOptions::new().write(true).create(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+impl Subscribers {
+    /// Listens for TCP connections at `address`.
+    pub fn tcp(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        log::info!("Listening on tcp://{}", listener.local_addr()?);
+        let subscribers = Self::default();
+        let clients = subscribers.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream.and_then(|stream| {
+                    stream.set_nodelay(true)?;
+                    stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT))?;
+                    Ok(stream)
+                }) {
+                    Ok(stream) => clients.push(stream),
+                    Err(err) => log::warn!("TCP log client failed: {}", err),
+                }
+            }
+        });
+        Ok(subscribers)
+    }
+
+    /// Listens for Unix domain socket connections at `path`.
+    ///
+    /// A stale socket file at `path` is removed.
+    pub fn unix(path: &Path) -> io::Result<Self> {
+        if symlink_metadata(path).map_or(false, |metadata| metadata.file_type().is_socket()) {
+            remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        log::info!("Listening on unix://{}", path.display());
+        let subscribers = Self::default();
+        let clients = subscribers.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream.and_then(|stream| {
+                    stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT))?;
+                    Ok(stream)
+                }) {
+                    Ok(stream) => clients.push(stream),
+                    Err(err) => log::warn!("Unix socket log client failed: {}", err),
+                }
+            }
+        });
+        Ok(subscribers)
+    }
+
+    fn push(&self, mut client: impl Write + Send + 'static) {
+        let (sender, receiver) = sync_channel::<Vec<u8>>(CLIENT_QUEUE_LEN);
+        thread::spawn(move || {
+            for data in receiver {
+                if let Err(err) = client.write_all(&data).and_then(|()| client.flush()) {
+                    log::info!("Log client disconnected: {}", err);
+                    break;
+                }
+            }
+        });
+        self.0.lock().unwrap().push(sender);
     }
 }
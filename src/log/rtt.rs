@@ -0,0 +1,164 @@
+//! SEGGER Real-Time Transfer.
+
+use super::{Options, Output, OutputMap};
+use crate::{cli::LogOutput, utils::search_rust_tool};
+use anyhow::{anyhow, bail, Result};
+use drone_config as config;
+use serde::Serialize;
+use std::{
+    io::{prelude::*, ErrorKind},
+    net::TcpStream,
+    path::Path,
+    process::Command,
+    sync::{mpsc::channel, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Symbol name of the RTT control block.
+pub const CONTROL_BLOCK_SYMBOL: &str = "_SEGGER_RTT";
+
+/// Size of the RTT control block header with the identifier and the buffer
+/// counts.
+pub const CONTROL_BLOCK_HEADER_SIZE: u32 = 24;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const CONNECT_RETRY: Duration = Duration::from_millis(100);
+
+/// Memory range to search the RTT control block in.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ControlBlock {
+    /// Start address.
+    pub address: u32,
+    /// Size of the range in bytes.
+    pub size: u32,
+}
+
+impl ControlBlock {
+    /// Locates the RTT control block.
+    ///
+    /// The address is taken from `log.rtt.control-block`, otherwise from the
+    /// `_SEGGER_RTT` symbol of the `firmware`, otherwise the whole RAM is
+    /// searched.
+    pub fn locate(config: &config::Config, firmware: Option<&Path>) -> Result<Self> {
+        let config_log_rtt = config
+            .log
+            .as_ref()
+            .and_then(|log| log.rtt.as_ref())
+            .ok_or_else(|| anyhow!("Missing `log.rtt` section in `{}`", config::CONFIG_NAME))?;
+        if let Some(address) = config_log_rtt.control_block {
+            return Ok(Self { address, size: CONTROL_BLOCK_HEADER_SIZE });
+        }
+        if let Some(firmware) = firmware {
+            if let Some(control_block) = Self::from_symbol(firmware)? {
+                return Ok(control_block);
+            }
+            log::warn!("`{}` symbol not found, searching RAM", CONTROL_BLOCK_SYMBOL);
+        }
+        Ok(Self { address: config.memory.ram.origin, size: config.memory.ram.size })
+    }
+
+    /// Returns the end address of the range.
+    pub fn end(self) -> u32 {
+        self.address.saturating_add(self.size)
+    }
+
+    fn from_symbol(firmware: &Path) -> Result<Option<Self>> {
+        let mut nm = Command::new(search_rust_tool("llvm-nm")?);
+        nm.arg("--print-size").arg("--defined-only").arg(firmware);
+        let output = nm.output()?;
+        if !output.status.success() {
+            bail!("`{:?}` failed: {}", nm, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        for line in String::from_utf8(output.stdout)?.lines() {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if let [address, size, _, CONTROL_BLOCK_SYMBOL] = fields.as_slice() {
+                return Ok(Some(Self {
+                    address: u32::from_str_radix(address, 16)?,
+                    size: u32::from_str_radix(size, 16)?.max(CONTROL_BLOCK_HEADER_SIZE),
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Checks that `options` are supported by RTT.
+pub fn check_options(options: &Options) -> Result<()> {
+    if options.timestamps || options.pc_sampling || options.exception_trace {
+        bail!("Timestamps and DWT trace are not supported by `rtt` log");
+    }
+    if options.record.is_some() {
+        bail!("Recording is not supported by `rtt` log");
+    }
+    Ok(())
+}
+
+/// Returns RTT channels selected by `outputs`. Channel 0 is selected if no
+/// output selects a specific one.
+pub fn channels(outputs: &[LogOutput]) -> Vec<u32> {
+    let mut channels =
+        outputs.iter().flat_map(|output| output.ports.iter().copied()).collect::<Vec<_>>();
+    channels.sort_unstable();
+    channels.dedup();
+    if channels.is_empty() {
+        channels.push(0);
+    }
+    channels
+}
+
+/// Connects to a TCP server at `port` on the local host, retrying until the
+/// server is up.
+pub fn connect(port: u16) -> Result<TcpStream> {
+    let start = Instant::now();
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => break Ok(stream),
+            Err(err) if start.elapsed() >= CONNECT_TIMEOUT => {
+                bail!("Couldn't connect to RTT server at port {}: {}", port, err)
+            }
+            Err(_) => thread::sleep(CONNECT_RETRY),
+        }
+    }
+}
+
+/// Runs log capture threads for RTT `channels`. Each channel is a raw byte
+/// stream written to the outputs of the same port.
+pub fn capture(
+    channels: Vec<(u8, Box<dyn Read + Send>)>,
+    outputs: Arc<[Output]>,
+    options: Options,
+) {
+    let (tx, rx) = channel();
+    for (port, mut input) in channels {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            loop {
+                match input.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(count) => {
+                        if tx.send((port, buf[..count].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => {
+                        log::error!("RTT channel {} capture failed: {}", port, err);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    thread::spawn(move || {
+        let health = Arc::clone(&options.health);
+        let outputs = OutputMap::new(&outputs, &options);
+        for (port, data) in rx {
+            log::debug!("Channel {} data {:?}", port, String::from_utf8_lossy(&data));
+            health.bytes(data.len());
+            health.packet();
+            outputs.write(port, &data);
+        }
+    });
+}
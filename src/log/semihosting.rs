@@ -0,0 +1,81 @@
+//! ARM® semihosting console serviced by GDB.
+
+use super::{Options, Output, OutputMap};
+use regex::Regex;
+use std::{
+    io::{prelude::*, BufReader},
+    mem::replace,
+    sync::Arc,
+    thread,
+    thread::JoinHandle,
+};
+
+/// Printed by the GDB script right before the target is resumed.
+const BEGIN_MARKER: &[u8] = b"--- drone semihosting begin ---";
+
+/// Printed by the GDB script when the target stops.
+const END_MARKER: &[u8] = b"--- drone semihosting end ---";
+
+/// Runs a thread routing the semihosting console output of GDB `stdout` to
+/// the `port` outputs.
+///
+/// GDB prints its own messages to the same `stdout`, so only the lines
+/// printed while the target is running, between the markers echoed by the GDB
+/// script, are routed. The rest is logged at debug level.
+///
+/// The thread returns the exit code of the target application if GDB
+/// reported it.
+pub fn capture(
+    stdout: impl Read + Send + 'static,
+    outputs: Arc<[Output]>,
+    options: Options,
+    port: u8,
+) -> JoinHandle<Option<i32>> {
+    thread::spawn(move || {
+        let exited =
+            Regex::new(r"\[Inferior \d+ \(.*\) exited (normally|with code (\d+))\]$").unwrap();
+        let outputs = OutputMap::new(&outputs, &options);
+        let mut running = false;
+        let mut continuing = false;
+        let mut exit_code = None;
+        for line in BufReader::new(stdout).split(b'\n') {
+            let mut line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    log::error!("Semihosting console capture failed: {}", err);
+                    break;
+                }
+            };
+            if line.ends_with(BEGIN_MARKER) {
+                running = true;
+                continuing = true;
+                continue;
+            }
+            if !running {
+                log::debug!("GDB: {}", String::from_utf8_lossy(&line));
+                continue;
+            }
+            if replace(&mut continuing, false) && line == b"Continuing." {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).into_owned();
+            if let Some(captures) = exited.captures(&text) {
+                // GDB prints the exit code in octal.
+                let code =
+                    captures.get(2).map_or(Ok(0), |code| i32::from_str_radix(code.as_str(), 8));
+                log::info!("Target application exited with code {:?}", code);
+                exit_code = code.ok();
+                running = false;
+                line.truncate(captures.get(0).unwrap().start());
+            } else if line.ends_with(END_MARKER) {
+                running = false;
+                line.truncate(line.len() - END_MARKER.len());
+            }
+            if running || !line.is_empty() {
+                line.push(b'\n');
+                outputs.write(port, &line);
+            }
+        }
+        exit_code
+    })
+}
@@ -1,8 +1,7 @@
 //! ARM® Single Wire Output protocol.
 
-use super::{Output, OutputMap};
-use anyhow::Result;
-use std::{ops::Generator, pin::Pin};
+use super::{dwt, health::Health, Options, Output, OutputMap};
+use std::{ops::Generator, pin::Pin, sync::Arc};
 
 enum Timestamp {
     Local { tc: u8 },
@@ -10,18 +9,47 @@ enum Timestamp {
     Global2,
 }
 
+/// Packet waiting for the local timestamp which follows it.
+enum Pending {
+    Source { port: u8, payload: Vec<u8> },
+    Hardware(dwt::Packet),
+}
+
 /// Creates a new ITM parser.
+///
+/// On a malformed packet the parser drops bytes until the next
+/// synchronization packet.
+///
+/// ITM emits a local timestamp packet after the packets it refers to. When
+/// timestamps or exception trace are enabled, source and hardware packets are
+/// held back until the next local timestamp (or synchronization packet) and
+/// stamped with its time. Local timestamps are deltas, which are accumulated
+/// into a 64-bit cycle counter. Global timestamps run on a different timebase
+/// and are ignored.
 #[allow(clippy::shadow_unrelated, clippy::too_many_lines)]
 pub fn parser(
     outputs: &[Output],
-) -> Pin<Box<dyn Generator<u8, Yield = (), Return = Result<!>> + '_>> {
+    options: Options,
+) -> Pin<Box<dyn Generator<u8, Yield = (), Return = !> + '_>> {
     fn recycle(bytes: &mut Vec<u8>, payload: &[u8]) {
         for &byte in payload.iter().rev() {
             bytes.push(byte);
         }
     }
-    let outputs = OutputMap::from(outputs);
+    fn desync(synced: &mut bool, health: &Health) {
+        health.bad_packet();
+        if *synced {
+            log::warn!("Lost synchronization");
+            *synced = false;
+        }
+    }
+    let outputs = OutputMap::new(outputs, &options);
+    let health = Arc::clone(&options.health);
+    let mut cycles = 0;
     let mut payload = Vec::with_capacity(8);
+    let stamped = options.timestamps || options.exception_trace;
+    let mut pending = Vec::new();
+    let mut synced = true;
     Box::pin(static move |byte: u8| {
         let mut bytes = vec![byte];
         loop {
@@ -36,20 +64,31 @@ pub fn parser(
                         if byte != 0 {
                             if zeros >= 47 {
                                 synchronization_packet(zeros);
+                                flush(&mut pending, cycles, &outputs, &options);
+                                if !synced {
+                                    log::warn!("Resynchronized");
+                                    health.resync();
+                                    synced = true;
+                                }
                             } else {
                                 log::warn!("Bad synchronization packet with {} zeros", zeros);
+                                desync(&mut synced, &health);
                                 recycle(&mut bytes, &payload);
                             }
                             break;
                         }
                     }
+                } else if !synced {
+                    health.dropped(1);
                 } else if byte == 0b0111_0000 {
                     log::warn!("Overflow");
+                    health.overflow();
                 } else if byte & 0b0000_1011 == 0b0000_1000 {
                     let sh = byte << 5 >> 7;
                     let ex = byte << 1 >> 5;
                     if byte >> 7 == 0 {
                         extension_packet(sh, ex, &[]);
+                        health.packet();
                         continue;
                     }
                     payload.clear();
@@ -58,9 +97,11 @@ pub fn parser(
                         payload.push(byte);
                         if byte >> 7 == 0 {
                             extension_packet(sh, ex, &payload);
+                            health.packet();
                             break;
                         } else if payload.len() == 4 {
                             log::warn!("Bad extension packet");
+                            desync(&mut synced, &health);
                             recycle(&mut bytes, &payload);
                             break;
                         }
@@ -71,7 +112,15 @@ pub fn parser(
                         && byte & 0b0111_0000 != 0b0111_0000
                     {
                         let payload = byte << 1 >> 5;
-                        timestamp_packet(&Timestamp::Local { tc: 0 }, &[payload]);
+                        timestamp_packet(
+                            &Timestamp::Local { tc: 0 },
+                            &[payload],
+                            &mut cycles,
+                            &mut pending,
+                            &outputs,
+                            &options,
+                        );
+                        health.packet();
                         continue;
                     } else if byte & 0b1100_1111 == 0b1100_0000 {
                         let tc = byte << 2 >> 6;
@@ -82,6 +131,7 @@ pub fn parser(
                         Timestamp::Global2
                     } else {
                         log::warn!("Invalid header");
+                        desync(&mut synced, &health);
                         continue;
                     };
                     payload.clear();
@@ -89,10 +139,19 @@ pub fn parser(
                         let byte = yield;
                         payload.push(byte);
                         if byte >> 7 == 0 {
-                            timestamp_packet(&kind, &payload);
+                            timestamp_packet(
+                                &kind,
+                                &payload,
+                                &mut cycles,
+                                &mut pending,
+                                &outputs,
+                                &options,
+                            );
+                            health.packet();
                             break;
                         } else if payload.len() == 4 {
                             log::warn!("Bad local timestamp packet");
+                            desync(&mut synced, &health);
                             recycle(&mut bytes, &payload);
                             break;
                         }
@@ -106,6 +165,7 @@ pub fn parser(
                         0b11 => 4,
                         _ => {
                             log::warn!("Invalid header");
+                            desync(&mut synced, &health);
                             continue;
                         }
                     };
@@ -113,7 +173,22 @@ pub fn parser(
                     while payload.len() < size {
                         payload.push(yield);
                     }
-                    source_packet(software, address, &payload, &outputs)?;
+                    if software && stamped {
+                        pending.push(Pending::Source { port: address, payload: payload.clone() });
+                        health.packet();
+                    } else if software {
+                        source_packet(address, &payload, &outputs);
+                        health.packet();
+                    } else if let Some(packet) = hardware_packet(address, &payload) {
+                        if stamped {
+                            pending.push(Pending::Hardware(packet));
+                        } else {
+                            dwt_event(packet, cycles, &options);
+                        }
+                        health.packet();
+                    } else {
+                        health.bad_packet();
+                    }
                 }
             } else {
                 bytes.push(yield);
@@ -130,10 +205,22 @@ fn extension_packet(sh: u8, ex: u8, payload: &[u8]) {
     log::debug!("Extension packet sh={}, ex={}, payload={:?}", sh, ex, payload);
 }
 
-fn timestamp_packet(timestamp: &Timestamp, payload: &[u8]) {
+fn timestamp_packet(
+    timestamp: &Timestamp,
+    payload: &[u8],
+    cycles: &mut u64,
+    pending: &mut Vec<Pending>,
+    outputs: &OutputMap<'_>,
+    options: &Options,
+) {
     match timestamp {
         Timestamp::Local { tc } => {
             log::debug!("Local timestamp tc={}, ts={:?}", tc, payload);
+            *cycles += payload
+                .iter()
+                .enumerate()
+                .fold(0, |value, (i, byte)| value | u64::from(byte & 0x7F) << (7 * i));
+            flush(pending, *cycles, outputs, options);
         }
         Timestamp::Global1 => {
             log::debug!("Global timestamp 1 ts={:?}", payload);
@@ -144,14 +231,34 @@ fn timestamp_packet(timestamp: &Timestamp, payload: &[u8]) {
     }
 }
 
-fn source_packet(software: bool, port: u8, payload: &[u8], outputs: &OutputMap<'_>) -> Result<()> {
-    log::debug!(
-        "Port {} {} packet {:?} {:?}",
-        port,
-        if software { "software" } else { "hardware" },
-        payload,
-        String::from_utf8_lossy(payload)
-    );
-    outputs.write(port, payload)?;
-    Ok(())
+/// Writes the packets held back until a timestamp, stamped with `cycles`.
+fn flush(pending: &mut Vec<Pending>, cycles: u64, outputs: &OutputMap<'_>, options: &Options) {
+    outputs.set_timestamp(cycles);
+    for packet in pending.drain(..) {
+        match packet {
+            Pending::Source { port, payload } => source_packet(port, &payload, outputs),
+            Pending::Hardware(packet) => dwt_event(packet, cycles, options),
+        }
+    }
+}
+
+fn source_packet(port: u8, payload: &[u8], outputs: &OutputMap<'_>) {
+    log::debug!("Port {} packet {:?} {:?}", port, payload, String::from_utf8_lossy(payload));
+    outputs.write(port, payload);
+}
+
+fn hardware_packet(id: u8, payload: &[u8]) -> Option<dwt::Packet> {
+    let packet = dwt::Packet::decode(id, payload);
+    if let Some(packet) = &packet {
+        log::debug!("Hardware packet {:?}", packet);
+    } else {
+        log::warn!("Unknown hardware packet id={}, payload={:?}", id, payload);
+    }
+    packet
+}
+
+fn dwt_event(packet: dwt::Packet, cycles: u64, options: &Options) {
+    if let Some(dwt_events) = &options.dwt_events {
+        dwt_events.send(dwt::Event { cycles, packet }).ok();
+    }
 }
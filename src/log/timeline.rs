@@ -0,0 +1,134 @@
+//! Exception timeline built from DWT exception trace.
+
+use super::dwt::{Event, ExceptionAction, Packet};
+use anyhow::Result;
+use serde_json::json;
+use std::{collections::BTreeMap, io::Write};
+
+/// Exception timeline.
+#[derive(Default)]
+pub struct Timeline {
+    spans: Vec<Span>,
+    active: Vec<(u16, u64)>,
+}
+
+/// Completed exception span.
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    /// Exception number.
+    pub number: u16,
+    /// Entry timestamp in core clock cycles.
+    pub start: u64,
+    /// Exit timestamp in core clock cycles.
+    pub end: u64,
+}
+
+/// Duration statistics of a single exception.
+#[derive(Clone, Copy, Debug)]
+pub struct Stats {
+    /// Number of completed spans.
+    pub count: u64,
+    /// Minimal duration in core clock cycles.
+    pub min: u64,
+    /// Maximal duration in core clock cycles.
+    pub max: u64,
+    /// Total duration in core clock cycles.
+    pub total: u64,
+}
+
+impl Timeline {
+    /// Feeds a DWT event into the timeline. Non-exception events are ignored.
+    pub fn push(&mut self, event: &Event) {
+        if let Packet::Exception { number, action } = event.packet {
+            match action {
+                ExceptionAction::Entered => {
+                    self.active.push((number, event.cycles));
+                }
+                ExceptionAction::Exited => {
+                    if let Some(index) = self.active.iter().rposition(|&(n, _)| n == number) {
+                        let (_, start) = self.active.remove(index);
+                        self.spans.push(Span { number, start, end: event.cycles });
+                    } else {
+                        log::debug!("Exit from exception {} without entry", number);
+                    }
+                }
+                ExceptionAction::Returned => {}
+            }
+        }
+    }
+
+    /// Returns completed spans.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// Returns per-exception duration statistics.
+    pub fn stats(&self) -> BTreeMap<u16, Stats> {
+        let mut stats = BTreeMap::new();
+        for span in &self.spans {
+            let duration = span.end - span.start;
+            let entry = stats.entry(span.number).or_insert(Stats {
+                count: 0,
+                min: u64::max_value(),
+                max: 0,
+                total: 0,
+            });
+            entry.count += 1;
+            entry.min = entry.min.min(duration);
+            entry.max = entry.max.max(duration);
+            entry.total += duration;
+        }
+        stats
+    }
+
+    /// Writes the timeline in Chrome Trace Event format.
+    ///
+    /// Timestamps are converted to microseconds if `core_clock` is known,
+    /// otherwise raw cycle counts are written.
+    pub fn write_chrome_trace(&self, w: &mut impl Write, core_clock: Option<u32>) -> Result<()> {
+        let scale = core_clock.map_or(1.0, |core_clock| 1_000_000.0 / f64::from(core_clock));
+        let events = self
+            .spans
+            .iter()
+            .map(|span| {
+                json!({
+                    "name": exception_name(span.number),
+                    "cat": "exception",
+                    "ph": "X",
+                    "ts": span.start as f64 * scale,
+                    "dur": (span.end - span.start) as f64 * scale,
+                    "pid": 0,
+                    "tid": 0,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_writer(w, &json!({ "traceEvents": events }))?;
+        Ok(())
+    }
+}
+
+impl Stats {
+    /// Average duration in core clock cycles.
+    pub fn avg(&self) -> f64 {
+        self.total as f64 / self.count as f64
+    }
+}
+
+/// Returns a human-readable name of the exception `number`.
+pub fn exception_name(number: u16) -> String {
+    match number {
+        0 => "Thread".into(),
+        1 => "Reset".into(),
+        2 => "NMI".into(),
+        3 => "HardFault".into(),
+        4 => "MemManage".into(),
+        5 => "BusFault".into(),
+        6 => "UsageFault".into(),
+        11 => "SVCall".into(),
+        12 => "DebugMonitor".into(),
+        14 => "PendSV".into(),
+        15 => "SysTick".into(),
+        16..=u16::MAX => format!("IRQ {}", number - 16),
+        _ => format!("Exception {}", number),
+    }
+}
@@ -0,0 +1,135 @@
+//! Log stop conditions.
+
+use super::{health::Health, Source, PORTS_COUNT, SOURCES_COUNT};
+use regex::Regex;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Conditions to stop logging on, shared between the capture threads and the
+/// command.
+#[derive(Debug)]
+pub struct Watch {
+    until: Option<Regex>,
+    fail_on: Option<Regex>,
+    deadline: Option<(Instant, Duration)>,
+    max_bytes: Option<u64>,
+    health: Arc<Health>,
+    lines: Mutex<Vec<Vec<u8>>>,
+    outcome: Mutex<Option<Outcome>>,
+}
+
+/// Reason to stop logging.
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// A line matched the `--until` pattern.
+    Passed(String),
+    /// A line matched the `--fail-on` pattern.
+    Failed(String),
+    /// The `--timeout` has passed.
+    TimedOut(Duration),
+    /// The `--max-bytes` limit has been reached.
+    MaxBytes(u64),
+    /// The log ended without matching the `--until` pattern.
+    Missed,
+}
+
+impl Watch {
+    /// Creates a new watch. The timeout is measured from this call.
+    pub fn new(
+        until: Option<Regex>,
+        fail_on: Option<Regex>,
+        timeout: Option<Duration>,
+        max_bytes: Option<u64>,
+        health: Arc<Health>,
+    ) -> Self {
+        Self {
+            until,
+            fail_on,
+            deadline: timeout.map(|timeout| (Instant::now(), timeout)),
+            max_bytes,
+            health,
+            lines: Mutex::new(vec![Vec::new(); PORTS_COUNT * SOURCES_COUNT]),
+            outcome: Mutex::new(None),
+        }
+    }
+
+    /// Matches complete lines of `data` written to `port` of `source` against
+    /// the patterns.
+    pub fn feed(&self, source: Option<Source>, port: u8, data: &[u8]) {
+        if self.until.is_none() && self.fail_on.is_none() {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap();
+        let line = &mut lines[Source::line_index(source, port)];
+        for &byte in data {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            let text = String::from_utf8_lossy(line).trim_end_matches('\r').to_string();
+            line.clear();
+            if self.fail_on.as_ref().map_or(false, |fail_on| fail_on.is_match(&text)) {
+                self.stop(Outcome::Failed(text));
+            } else if self.until.as_ref().map_or(false, |until| until.is_match(&text)) {
+                self.stop(Outcome::Passed(text));
+            }
+        }
+    }
+
+    /// Returns the reason to stop logging, if any.
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some((start, timeout)) = self.deadline {
+            if start.elapsed() >= timeout {
+                self.stop(Outcome::TimedOut(timeout));
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if self.health.summary().bytes >= max_bytes {
+                self.stop(Outcome::MaxBytes(max_bytes));
+            }
+        }
+        self.outcome.lock().unwrap().clone()
+    }
+
+    /// Returns the final outcome after the log has ended.
+    pub fn finish(&self) -> Option<Outcome> {
+        self.outcome().or_else(|| self.until.as_ref().map(|_| Outcome::Missed))
+    }
+
+    /// Signals to stop logging with `outcome`, unless already stopped.
+    pub fn stop(&self, outcome: Outcome) {
+        let mut current = self.outcome.lock().unwrap();
+        if current.is_none() {
+            log::debug!("Stopping log: {}", outcome);
+            *current = Some(outcome);
+        }
+    }
+}
+
+impl Outcome {
+    /// Returns the exit status of the command.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Passed(_) => 0,
+            Self::Failed(_) => 2,
+            Self::TimedOut(_) => 3,
+            Self::MaxBytes(_) => 4,
+            Self::Missed => 5,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passed(line) => write!(f, "matched `--until` pattern: {}", line),
+            Self::Failed(line) => write!(f, "matched `--fail-on` pattern: {}", line),
+            Self::TimedOut(timeout) => write!(f, "timed out after {:?}", timeout),
+            Self::MaxBytes(max_bytes) => write!(f, "reached the limit of {} bytes", max_bytes),
+            Self::Missed => write!(f, "log ended without matching `--until` pattern"),
+        }
+    }
+}
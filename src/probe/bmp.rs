@@ -1,21 +1,28 @@
 //! Black Magic Probe.
 
 use super::{
-    begin_log_output, gdb_script_command, gdb_script_continue, gdb_script_wait, run_gdb_client,
-    rustc_substitute_path, setup_serial_endpoint,
+    begin_log_output, capture_swo_dso, gdb_script_command, gdb_script_continue, gdb_script_finish,
+    gdb_script_wait, run_gdb_client, rustc_substitute_path, spawn_gdb_script, swo_source,
 };
 use crate::{
     cli::{FlashCmd, GdbCmd, LogCmd, ResetCmd},
     color::Color,
     log,
+    log::rtt::ControlBlock,
+    serial,
     templates::Registry,
-    utils::{block_with_signals, exhaust_fifo, make_fifo, run_command, spawn_command, temp_dir},
+    utils::{block_with_signals, exhaust_fifo, make_fifo, run_command, temp_dir},
 };
-use anyhow::Result;
-use drone_config as config;
+use anyhow::{anyhow, Result};
+use drone_config::{self as config, SerialFlowControl, SerialParity};
 use signal_hook::iterator::Signals;
+use std::{fs::File, sync::Arc};
 use tempfile::tempdir_in;
 
+/// Baud rate of the BMP auxiliary serial port, which is a USB CDC device and
+/// doesn't depend on it.
+const RTT_BAUD_RATE: u32 = 115_200;
+
 /// Runs `drone reset` command.
 pub fn reset(
     cmd: ResetCmd,
@@ -64,32 +71,85 @@ pub fn gdb(
 /// Runs `drone log` command.
 pub fn log_swo_serial(
     cmd: LogCmd,
+    options: log::Options,
     signals: Signals,
     registry: Registry<'_>,
     config: config::Config,
     color: Color,
 ) -> Result<()> {
-    let LogCmd { reset, outputs } = cmd;
+    let LogCmd { reset, outputs, semihosting_port, .. } = cmd;
+    let options = log::Options { source: swo_source(&config), ..options };
     let config_log_swo = config.log.as_ref().unwrap().swo.as_ref().unwrap();
     let serial_endpoint = config_log_swo.serial_endpoint.as_ref().unwrap();
 
     let dir = tempdir_in(temp_dir())?;
     let pipe = make_fifo(&dir, "pipe")?;
     let ports = outputs.iter().flat_map(|output| output.ports.iter().copied()).collect();
-    let script = registry.bmp_swo(&config, &ports, reset, &pipe)?;
-    let mut gdb = spawn_command(gdb_script_command(&config, None, script.path()))?;
+    let script = registry.bmp_swo(&config, &ports, reset, &options, &pipe)?;
+    let log_outputs = log::Output::open_all(&outputs, &options)?;
+    let (gdb, semihosting) =
+        spawn_gdb_script(&config, script.path(), &log_outputs, &options, semihosting_port)?;
 
-    let (pipe, packet) = gdb_script_wait(&signals, pipe)?;
-    setup_serial_endpoint(&signals, serial_endpoint, config_log_swo.baud_rate)?;
+    let watch = options.watch.clone();
+    let (gdb, handshake) = gdb_script_wait(&signals, gdb, pipe, watch.clone())?;
+    let (pipe, packet) = match handshake {
+        Some(handshake) => handshake,
+        None => return gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch),
+    };
+    serial::configure(
+        serial_endpoint,
+        config_log_swo.baud_rate,
+        config_log_swo.parity,
+        config_log_swo.flow_control,
+    )?;
     exhaust_fifo(serial_endpoint)?;
-    log::capture(serial_endpoint.into(), log::Output::open_all(&outputs)?, log::swo::parser);
+    capture_swo_dso(&config, &log_outputs, &options)?;
+    log::capture(serial_endpoint.into(), Arc::clone(&log_outputs), options, log::swo::parser);
     begin_log_output(color);
     gdb_script_continue(&signals, pipe, packet)?;
 
-    block_with_signals(&signals, true, move || {
-        gdb.wait()?;
-        Ok(())
+    gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch)
+}
+
+/// Runs `drone log` command.
+pub fn log_rtt(
+    cmd: LogCmd,
+    options: log::Options,
+    signals: Signals,
+    registry: Registry<'_>,
+    config: config::Config,
+    color: Color,
+) -> Result<()> {
+    let LogCmd { reset, firmware, outputs, semihosting_port, .. } = cmd;
+    log::rtt::check_options(&options)?;
+    let config_log_rtt = config.log.as_ref().unwrap().rtt.as_ref().unwrap();
+    let serial_endpoint = config_log_rtt.serial_endpoint.as_ref().ok_or_else(|| {
+        anyhow!("Missing `log.rtt.serial-endpoint` field in `{}`", config::CONFIG_NAME)
     })?;
+    let control_block = ControlBlock::locate(&config, firmware.as_deref())?;
+
+    let dir = tempdir_in(temp_dir())?;
+    let pipe = make_fifo(&dir, "pipe")?;
+    let script = registry.bmp_rtt(&config, reset, control_block, &pipe)?;
+    let log_outputs = log::Output::open_all(&outputs, &options)?;
+    let (gdb, semihosting) =
+        spawn_gdb_script(&config, script.path(), &log_outputs, &options, semihosting_port)?;
+
+    let watch = options.watch.clone();
+    let (gdb, handshake) = gdb_script_wait(&signals, gdb, pipe, watch.clone())?;
+    let (pipe, packet) = match handshake {
+        Some(handshake) => handshake,
+        None => return gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch),
+    };
+    serial::configure(serial_endpoint, RTT_BAUD_RATE, SerialParity::None, SerialFlowControl::None)?;
+    exhaust_fifo(serial_endpoint)?;
+    log::rtt::capture(
+        vec![(0, Box::new(File::open(serial_endpoint)?))],
+        Arc::clone(&log_outputs),
+        options,
+    );
+    begin_log_output(color);
+    gdb_script_continue(&signals, pipe, packet)?;
 
-    Ok(())
+    gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch)
 }
@@ -1,25 +1,28 @@
 //! Segger J-Link.
 
 use super::{
-    begin_log_output, gdb_script_command, gdb_script_continue, gdb_script_wait, run_gdb_client,
-    run_gdb_server, rustc_substitute_path, setup_serial_endpoint,
+    begin_log_output, gdb_script_continue, gdb_script_finish, gdb_script_wait, run_gdb_client,
+    run_gdb_server, rustc_substitute_path, spawn_gdb_script,
 };
 use crate::{
     cli::{FlashCmd, GdbCmd, LogCmd, ResetCmd},
     color::Color,
     log,
+    log::rtt::ControlBlock,
+    serial,
     templates::Registry,
-    utils::{
-        block_with_signals, exhaust_fifo, make_fifo, run_command, search_rust_tool, spawn_command,
-        temp_dir,
-    },
+    utils::{block_with_signals, exhaust_fifo, make_fifo, run_command, search_rust_tool, temp_dir},
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
 use drone_config as config;
 use signal_hook::iterator::Signals;
-use std::{fs, os::unix::fs::PermissionsExt, path::Path, process::Command};
+use std::{
+    fs, fs::File, io::stdin, os::unix::fs::PermissionsExt, path::Path, process::Command, sync::Arc,
+};
 use tempfile::tempdir_in;
 
+const RTT_TELNET_PORT: u16 = 19021;
+
 /// Runs `drone reset` command.
 pub fn reset(
     cmd: ResetCmd,
@@ -90,12 +93,19 @@ pub fn gdb(
 /// Runs `drone log` command.
 pub fn log_dso_serial(
     cmd: LogCmd,
+    options: log::Options,
     signals: Signals,
     registry: Registry<'_>,
     config: config::Config,
     color: Color,
 ) -> Result<()> {
-    let LogCmd { reset, outputs } = cmd;
+    let LogCmd { reset, outputs, stdin_port, stdin_file, semihosting_port, .. } = cmd;
+    if let Some(port) = stdin_port.filter(|&port| usize::from(port) >= log::PORTS_COUNT) {
+        bail!("Port {} is out of range", port);
+    }
+    if options.timestamps || options.pc_sampling || options.exception_trace {
+        bail!("Timestamps and DWT trace are not supported by `dsoserial` log");
+    }
     let config_probe_jlink = config.probe.as_ref().unwrap().jlink.as_ref().unwrap();
     let config_log_dso = config.log.as_ref().unwrap().dso.as_ref().unwrap();
 
@@ -108,25 +118,83 @@ pub fn log_dso_serial(
     let pipe = make_fifo(&dir, "pipe")?;
     let ports = outputs.iter().flat_map(|output| output.ports.iter().copied()).collect();
     let script = registry.jlink_dso(&config, &ports, reset, &pipe)?;
-    let mut gdb = spawn_command(gdb_script_command(&config, None, script.path()))?;
-
-    let (pipe, packet) = gdb_script_wait(&signals, pipe)?;
-    setup_serial_endpoint(&signals, &config_log_dso.serial_endpoint, config_log_dso.baud_rate)?;
+    let log_outputs = log::Output::open_all(&outputs, &options)?;
+    let (gdb, semihosting) =
+        spawn_gdb_script(&config, script.path(), &log_outputs, &options, semihosting_port)?;
+
+    let watch = options.watch.clone();
+    let (gdb, handshake) = gdb_script_wait(&signals, gdb, pipe, watch.clone())?;
+    let (pipe, packet) = match handshake {
+        Some(handshake) => handshake,
+        None => return gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch),
+    };
+    serial::configure(
+        &config_log_dso.serial_endpoint,
+        config_log_dso.baud_rate,
+        config_log_dso.parity,
+        config_log_dso.flow_control,
+    )?;
     exhaust_fifo(&config_log_dso.serial_endpoint)?;
     log::capture(
         config_log_dso.serial_endpoint.clone().into(),
-        log::Output::open_all(&outputs)?,
+        Arc::clone(&log_outputs),
+        options,
         log::dso::parser,
     );
+    if let Some(port) = stdin_port {
+        let endpoint = config_log_dso.serial_endpoint.clone().into();
+        match stdin_file {
+            Some(path) => log::dso::forward(File::open(path)?, endpoint, port),
+            None => log::dso::forward(stdin(), endpoint, port),
+        }
+    }
     begin_log_output(color);
     gdb_script_continue(&signals, pipe, packet)?;
 
-    block_with_signals(&signals, true, move || {
-        gdb.wait()?;
-        Ok(())
-    })?;
+    gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch)
+}
+
+/// Runs `drone log` command.
+pub fn log_rtt(
+    cmd: LogCmd,
+    options: log::Options,
+    signals: Signals,
+    registry: Registry<'_>,
+    config: config::Config,
+    color: Color,
+) -> Result<()> {
+    let LogCmd { reset, firmware, outputs, semihosting_port, .. } = cmd;
+    log::rtt::check_options(&options)?;
+    let config_probe_jlink = config.probe.as_ref().unwrap().jlink.as_ref().unwrap();
+    let config_log_rtt = config.log.as_ref().unwrap().rtt.as_ref().unwrap();
+    let server_port = config_log_rtt.server_port.unwrap_or(RTT_TELNET_PORT);
+    let control_block = ControlBlock::locate(&config, firmware.as_deref())?;
+
+    let mut gdb_server = Command::new(&config_probe_jlink.gdb_server_command);
+    jlink_args(&mut gdb_server, config_probe_jlink);
+    gdb_server_args(&mut gdb_server, config_probe_jlink);
+    gdb_server.arg("-RTTTelnetPort").arg(server_port.to_string());
+    let _gdb_server = run_gdb_server(gdb_server, None)?;
+
+    let dir = tempdir_in(temp_dir())?;
+    let pipe = make_fifo(&dir, "pipe")?;
+    let script = registry.jlink_rtt(&config, reset, control_block, &pipe)?;
+    let log_outputs = log::Output::open_all(&outputs, &options)?;
+    let (gdb, semihosting) =
+        spawn_gdb_script(&config, script.path(), &log_outputs, &options, semihosting_port)?;
+
+    let watch = options.watch.clone();
+    let (gdb, handshake) = gdb_script_wait(&signals, gdb, pipe, watch.clone())?;
+    let (pipe, packet) = match handshake {
+        Some(handshake) => handshake,
+        None => return gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch),
+    };
+    let stream = log::rtt::connect(server_port)?;
+    log::rtt::capture(vec![(0, Box::new(stream))], Arc::clone(&log_outputs), options);
+    begin_log_output(color);
+    gdb_script_continue(&signals, pipe, packet)?;
 
-    Ok(())
+    gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch)
 }
 
 fn jlink_args(jlink: &mut Command, config_probe_jlink: &config::ProbeJlink) {
@@ -5,10 +5,16 @@ pub mod jlink;
 pub mod openocd;
 
 use crate::{
-    cli::{FlashCmd, GdbCmd, LogCmd, ResetCmd},
+    cli::{FlashCmd, GdbCmd, LogCmd, LogOutput, ResetCmd},
     color::Color,
+    log,
+    log::watch::Watch,
+    serial,
     templates::Registry,
-    utils::{block_with_signals, detach_pgid, finally, run_command, spawn_command},
+    utils::{
+        block_with_signals, detach_pgid, exhaust_fifo, finally, run_command, ser_to_string,
+        spawn_command, ExitCodeError,
+    },
 };
 use ansi_term::Color::Cyan;
 use anyhow::{anyhow, bail, Error, Result};
@@ -21,10 +27,18 @@ use std::{
     fs::OpenOptions,
     io::{prelude::*, BufRead, BufReader},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{channel, RecvTimeoutError},
+        Arc,
+    },
     thread,
+    thread::JoinHandle,
+    time::Duration,
 };
 
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
 /// An `enum` of all supported debug probes.
 #[allow(missing_docs)]
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -45,6 +59,8 @@ pub enum Log {
     SwoSerial,
     /// Drone Serial Output through USB-serial adapter.
     DsoSerial,
+    /// SEGGER RTT through debug probe memory access.
+    Rtt,
 }
 
 impl<'a> TryFrom<&'a config::Config> for Probe {
@@ -86,13 +102,18 @@ impl<'a> TryFrom<&'a config::Config> for Log {
             }
         } else if config_log.dso.is_some() {
             Ok(Self::DsoSerial)
+        } else if config_log.rtt.is_some() {
+            Ok(Self::Rtt)
         } else {
-            bail!("Missing one of `log.swo`, `log.dso` sections in `{}`", config::CONFIG_NAME);
+            bail!(
+                "Missing one of `log.swo`, `log.dso`, `log.rtt` sections in `{}`",
+                config::CONFIG_NAME
+            );
         }
     }
 }
 
-type LogFn = fn(LogCmd, Signals, Registry<'_>, config::Config, Color) -> Result<()>;
+type LogFn = fn(LogCmd, log::Options, Signals, Registry<'_>, config::Config, Color) -> Result<()>;
 type ResetFn = fn(ResetCmd, Signals, Registry<'_>, config::Config) -> Result<()>;
 type FlashFn = fn(FlashCmd, Signals, Registry<'_>, config::Config) -> Result<()>;
 type GdbFn = fn(GdbCmd, Signals, Registry<'_>, config::Config) -> Result<()>;
@@ -128,22 +149,42 @@ pub fn gdb(probe: Probe) -> GdbFn {
 pub fn log(probe: Probe, log: Log) -> Option<LogFn> {
     match (probe, log) {
         (Probe::Bmp, Log::SwoSerial) => Some(bmp::log_swo_serial),
+        (Probe::Bmp, Log::Rtt) => Some(bmp::log_rtt),
         (Probe::Jlink, Log::DsoSerial) => Some(jlink::log_dso_serial),
+        (Probe::Jlink, Log::Rtt) => Some(jlink::log_rtt),
         (Probe::Openocd, Log::SwoProbe) | (Probe::Openocd, Log::SwoSerial) => {
             Some(openocd::log_swo)
         }
+        (Probe::Openocd, Log::Rtt) => Some(openocd::log_rtt),
         _ => None,
     }
 }
 
-/// Configures the endpoint with `stty` command.
-pub fn setup_serial_endpoint(signals: &Signals, endpoint: &str, baud_rate: u32) -> Result<()> {
-    let mut stty = Command::new("stty");
-    stty.arg(format!("--file={}", endpoint));
-    stty.arg("speed");
-    stty.arg(format!("{}", baud_rate));
-    stty.arg("raw");
-    block_with_signals(signals, true, || run_command(stty))
+/// Returns a function to serve `drone log` command, or an error if `log` is
+/// not supported by `probe`.
+pub fn log_fn(probe: Probe, log: Log) -> Result<LogFn> {
+    self::log(probe, log).ok_or_else(|| {
+        anyhow!(
+            "`{}` log with `{}` probe is not supported",
+            ser_to_string(probe),
+            ser_to_string(log)
+        )
+    })
+}
+
+/// Checks that `outputs` select only RTT channels supported by `probe`.
+pub fn check_rtt_channels(probe: Probe, log: Log, outputs: &[LogOutput]) -> Result<()> {
+    if matches!(log, Log::Rtt) && !matches!(probe, Probe::Openocd) {
+        let channels = log::rtt::channels(outputs);
+        if channels != [0] {
+            bail!(
+                "Only RTT channel 0 is supported by `{}` probe, selected channels: {:?}",
+                ser_to_string(probe),
+                channels
+            );
+        }
+    }
+    Ok(())
 }
 
 /// Runs a GDB server.
@@ -208,12 +249,149 @@ pub fn gdb_script_command(
     gdb
 }
 
+/// Spawns a GDB script command for `drone log`.
+///
+/// If semihosting is enabled, the console output is routed to the
+/// `semihosting_port` of `outputs`.
+pub fn spawn_gdb_script(
+    config: &config::Config,
+    script: &Path,
+    outputs: &Arc<[log::Output]>,
+    options: &log::Options,
+    semihosting_port: u8,
+) -> Result<(Child, Option<JoinHandle<Option<i32>>>)> {
+    let mut gdb = gdb_script_command(config, None, script);
+    if !config.probe.as_ref().unwrap().semihosting {
+        return Ok((spawn_command(gdb)?, None));
+    }
+    gdb.stdout(Stdio::piped());
+    let mut gdb = spawn_command(gdb)?;
+    let stdout = gdb.stdout.take().unwrap();
+    let semihosting =
+        log::semihosting::capture(stdout, Arc::clone(outputs), options.clone(), semihosting_port);
+    Ok((gdb, Some(semihosting)))
+}
+
+/// Returns the source tag of the SWO input. The tag is set only if `log.dso`
+/// is configured in addition to `log.swo`, in which case both are captured in
+/// the same session.
+pub fn swo_source(config: &config::Config) -> Option<log::Source> {
+    config.log.as_ref().and_then(|log| log.dso.as_ref()).map(|_| log::Source::Swo)
+}
+
+/// Starts capturing DSO into the same `outputs` as SWO, if `log.dso` is
+/// configured in addition to `log.swo`.
+///
+/// The raw DSO stream is recorded to the `--record` path with `.dso`
+/// appended.
+pub fn capture_swo_dso(
+    config: &config::Config,
+    outputs: &Arc<[log::Output]>,
+    options: &log::Options,
+) -> Result<()> {
+    let config_log_dso = match config.log.as_ref().and_then(|log| log.dso.as_ref()) {
+        Some(config_log_dso) => config_log_dso,
+        None => return Ok(()),
+    };
+    serial::configure(
+        &config_log_dso.serial_endpoint,
+        config_log_dso.baud_rate,
+        config_log_dso.parity,
+        config_log_dso.flow_control,
+    )?;
+    exhaust_fifo(&config_log_dso.serial_endpoint)?;
+    let record = options.record.as_ref().map(|path| {
+        let mut path = path.clone().into_os_string();
+        path.push(".dso");
+        PathBuf::from(path)
+    });
+    let options = log::Options {
+        timestamps: false,
+        record,
+        source: Some(log::Source::Dso),
+        ..options.clone()
+    };
+    log::capture(
+        config_log_dso.serial_endpoint.clone().into(),
+        Arc::clone(outputs),
+        options,
+        log::dso::parser,
+    );
+    Ok(())
+}
+
+/// Waits for the GDB script command to finish.
+///
+/// If `watch` signals to stop logging, the GDB script command is killed. The
+/// pending partial lines of `outputs` are written out afterwards. A non-zero
+/// exit code of the target application reported through semihosting becomes
+/// the exit status.
+pub fn gdb_script_finish(
+    signals: &Signals,
+    mut gdb: Child,
+    semihosting: Option<JoinHandle<Option<i32>>>,
+    outputs: &[log::Output],
+    watch: Option<Arc<Watch>>,
+) -> Result<()> {
+    block_with_signals(signals, true, move || {
+        if let Some(watch) = watch {
+            while gdb.try_wait()?.is_none() {
+                if watch.outcome().is_some() {
+                    gdb.kill()?;
+                    gdb.wait()?;
+                    break;
+                }
+                thread::sleep(WATCH_INTERVAL);
+            }
+        } else {
+            gdb.wait()?;
+        }
+        Ok(())
+    })?;
+    log::Output::flush_all(outputs);
+    match semihosting.and_then(|semihosting| semihosting.join().ok().flatten()) {
+        Some(code) if code != 0 => Err(ExitCodeError(code).into()),
+        _ => Ok(()),
+    }
+}
+
+/// GDB script pipe with the packet received from the other side.
+pub type Handshake = (PathBuf, [u8; 1]);
+
 /// Waits for the other side of `pipe`.
-pub fn gdb_script_wait(signals: &Signals, pipe: PathBuf) -> Result<(PathBuf, [u8; 1])> {
-    block_with_signals(&signals, false, move || {
+///
+/// If `watch` signals to stop logging before that, the GDB script command is
+/// killed and `None` is returned, so that a hanging connect, flash, or reset
+/// doesn't defeat the stop conditions.
+pub fn gdb_script_wait(
+    signals: &Signals,
+    mut gdb: Child,
+    pipe: PathBuf,
+    watch: Option<Arc<Watch>>,
+) -> Result<(Child, Option<Handshake>)> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
         let mut packet = [0];
-        OpenOptions::new().read(true).open(&pipe)?.read_exact(&mut packet)?;
-        Ok((pipe, packet))
+        let result =
+            OpenOptions::new().read(true).open(&pipe).and_then(|mut p| p.read_exact(&mut packet));
+        tx.send(result.map(|()| (pipe, packet))).ok();
+    });
+    block_with_signals(&signals, false, move || {
+        loop {
+            match rx.recv_timeout(WATCH_INTERVAL) {
+                Ok(handshake) => break Ok((gdb, Some(handshake?))),
+                Err(RecvTimeoutError::Disconnected) => bail!("channel is broken"),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if watch.as_ref().map_or(false, |watch| watch.outcome().is_some()) {
+                gdb.kill()?;
+                gdb.wait()?;
+                break Ok((gdb, None));
+            }
+            if let Some(status) = gdb.try_wait()? {
+                bail!("GDB exited before starting the session: {}", status);
+            }
+        }
     })
 }
 
@@ -1,22 +1,26 @@
 //! OpenOCD.
 
 use super::{
-    begin_log_output, gdb_script_command, gdb_script_continue, gdb_script_wait, run_gdb_client,
-    run_gdb_server, rustc_substitute_path, setup_serial_endpoint,
+    begin_log_output, capture_swo_dso, gdb_script_continue, gdb_script_finish, gdb_script_wait,
+    run_gdb_client, run_gdb_server, rustc_substitute_path, spawn_gdb_script, swo_source,
 };
 use crate::{
     cli::{FlashCmd, GdbCmd, LogCmd, ResetCmd},
     color::Color,
     log,
+    log::rtt::ControlBlock,
+    serial,
     templates::Registry,
-    utils::{block_with_signals, exhaust_fifo, make_fifo, run_command, spawn_command, temp_dir},
+    utils::{block_with_signals, exhaust_fifo, make_fifo, run_command, temp_dir},
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use drone_config as config;
 use signal_hook::iterator::Signals;
-use std::process::Command;
+use std::{convert::TryFrom, io::Read, process::Command, sync::Arc};
 use tempfile::tempdir_in;
 
+const RTT_SERVER_PORT: u16 = 9090;
+
 /// Runs `drone reset` command.
 pub fn reset(
     cmd: ResetCmd,
@@ -79,12 +83,14 @@ pub fn gdb(
 /// Runs `drone log` command.
 pub fn log_swo(
     cmd: LogCmd,
+    options: log::Options,
     signals: Signals,
     registry: Registry<'_>,
     config: config::Config,
     color: Color,
 ) -> Result<()> {
-    let LogCmd { reset, outputs } = cmd;
+    let LogCmd { reset, outputs, semihosting_port, .. } = cmd;
+    let options = log::Options { source: swo_source(&config), ..options };
     let config_probe_openocd = config.probe.as_ref().unwrap().openocd.as_ref().unwrap();
     let config_log_swo = config.log.as_ref().unwrap().swo.as_ref().unwrap();
 
@@ -100,27 +106,94 @@ pub fn log_swo(
     let input;
     let script;
     if let Some(serial_endpoint) = &config_log_swo.serial_endpoint {
-        setup_serial_endpoint(&signals, serial_endpoint, config_log_swo.baud_rate)?;
+        serial::configure(
+            serial_endpoint,
+            config_log_swo.baud_rate,
+            config_log_swo.parity,
+            config_log_swo.flow_control,
+        )?;
         exhaust_fifo(serial_endpoint)?;
         input = serial_endpoint.into();
-        script = registry.openocd_swo(&config, &ports, reset, &pipe, None)?;
+        script = registry.openocd_swo(&config, &ports, reset, &options, &pipe, None)?;
     } else {
         input = make_fifo(&dir, "input")?;
-        script = registry.openocd_swo(&config, &ports, reset, &pipe, Some(&input))?;
+        script = registry.openocd_swo(&config, &ports, reset, &options, &pipe, Some(&input))?;
     }
-    log::capture(input, log::Output::open_all(&outputs)?, log::swo::parser);
-    let mut gdb = spawn_command(gdb_script_command(&config, None, script.path()))?;
+    let log_outputs = log::Output::open_all(&outputs, &options)?;
+    let (gdb, semihosting) =
+        spawn_gdb_script(&config, script.path(), &log_outputs, &options, semihosting_port)?;
+    let watch = options.watch.clone();
+    let dso_options = options.clone();
+    log::capture(input, Arc::clone(&log_outputs), options, log::swo::parser);
 
-    let (pipe, packet) = gdb_script_wait(&signals, pipe)?;
+    let (gdb, handshake) = gdb_script_wait(&signals, gdb, pipe, watch.clone())?;
+    let (pipe, packet) = match handshake {
+        Some(handshake) => handshake,
+        None => return gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch),
+    };
+    capture_swo_dso(&config, &log_outputs, &dso_options)?;
     begin_log_output(color);
     gdb_script_continue(&signals, pipe, packet)?;
 
-    block_with_signals(&signals, true, move || {
-        gdb.wait()?;
-        Ok(())
-    })?;
+    gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch)
+}
+
+/// Runs `drone log` command.
+pub fn log_rtt(
+    cmd: LogCmd,
+    options: log::Options,
+    signals: Signals,
+    registry: Registry<'_>,
+    config: config::Config,
+    color: Color,
+) -> Result<()> {
+    let LogCmd { reset, firmware, outputs, semihosting_port, .. } = cmd;
+    log::rtt::check_options(&options)?;
+    let config_probe_openocd = config.probe.as_ref().unwrap().openocd.as_ref().unwrap();
+    let config_log_rtt = config.log.as_ref().unwrap().rtt.as_ref().unwrap();
+    let server_port = config_log_rtt.server_port.unwrap_or(RTT_SERVER_PORT);
+    let control_block = ControlBlock::locate(&config, firmware.as_deref())?;
+    let servers = log::rtt::channels(&outputs)
+        .into_iter()
+        .map(|channel| {
+            u16::try_from(channel)
+                .ok()
+                .and_then(|offset| server_port.checked_add(offset))
+                .map(|port| (channel, port))
+                .ok_or_else(|| anyhow!("RTT channel {} is out of range", channel))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let commands = registry.openocd_gdb_openocd(&config)?;
+    let mut openocd = Command::new(&config_probe_openocd.command);
+    openocd_arguments(&mut openocd, config_probe_openocd);
+    openocd_commands(&mut openocd, &commands);
+    let _openocd = run_gdb_server(openocd, None)?;
+
+    let dir = tempdir_in(temp_dir())?;
+    let pipe = make_fifo(&dir, "pipe")?;
+    let script = registry.openocd_rtt(&config, reset, control_block, &servers, &pipe)?;
+    let log_outputs = log::Output::open_all(&outputs, &options)?;
+    let (gdb, semihosting) =
+        spawn_gdb_script(&config, script.path(), &log_outputs, &options, semihosting_port)?;
+
+    let watch = options.watch.clone();
+    let (gdb, handshake) = gdb_script_wait(&signals, gdb, pipe, watch.clone())?;
+    let (pipe, packet) = match handshake {
+        Some(handshake) => handshake,
+        None => return gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch),
+    };
+    let mut channels = Vec::new();
+    for &(channel, port) in &servers {
+        #[allow(clippy::cast_possible_truncation)]
+        let stream: Box<dyn Read + Send> = Box::new(log::rtt::connect(port)?);
+        channels.push((channel as u8, stream));
+    }
+    log::rtt::capture(channels, Arc::clone(&log_outputs), options);
+    begin_log_output(color);
+    gdb_script_continue(&signals, pipe, packet)?;
 
-    Ok(())
+    gdb_script_finish(&signals, gdb, semihosting, &log_outputs, watch)
 }
 
 fn openocd_arguments(openocd: &mut Command, config_probe_openocd: &config::ProbeOpenocd) {
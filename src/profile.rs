@@ -0,0 +1,109 @@
+//! Statistical PC-sampling profiler.
+
+use crate::utils::search_rust_tool;
+use anyhow::{bail, Result};
+use regex::Regex;
+use std::{collections::HashMap, io::Write, path::Path, process::Command};
+
+const SLEEP: &str = "[sleep]";
+
+/// Function symbols of a firmware.
+pub struct Symbols(Vec<Symbol>);
+
+struct Symbol {
+    address: u32,
+    size: u32,
+    name: String,
+}
+
+/// Collected PC samples.
+#[derive(Default)]
+pub struct Profile {
+    samples: HashMap<String, u32>,
+    total: u32,
+}
+
+impl Symbols {
+    /// Reads function symbols from the `firmware` ELF file with `llvm-nm`.
+    pub fn read(firmware: &Path) -> Result<Self> {
+        let mut nm = Command::new(search_rust_tool("llvm-nm")?);
+        nm.arg("--print-size").arg("--defined-only").arg("--demangle").arg(firmware);
+        let output = nm.output()?;
+        if !output.status.success() {
+            bail!("`{:?}` failed: {}", nm, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        let hash = Regex::new("::h[0-9a-f]{16}$").unwrap();
+        let mut symbols = Vec::new();
+        for line in String::from_utf8(output.stdout)?.lines() {
+            let fields = line.splitn(4, ' ').collect::<Vec<_>>();
+            if let [address, size, kind, name] = fields.as_slice() {
+                if !["t", "T", "w", "W"].contains(kind) {
+                    continue;
+                }
+                symbols.push(Symbol {
+                    address: u32::from_str_radix(address, 16)? & !1,
+                    size: u32::from_str_radix(size, 16)?,
+                    name: hash.replace(name, "").into_owned(),
+                });
+            }
+        }
+        symbols.sort_by_key(|symbol| symbol.address);
+        Ok(Self(symbols))
+    }
+
+    /// Returns the name of the function containing `pc`.
+    pub fn lookup(&self, pc: u32) -> Option<&str> {
+        let index = match self.0.binary_search_by_key(&pc, |symbol| symbol.address) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let symbol = &self.0[index];
+        (pc - symbol.address < symbol.size).then_some(symbol.name.as_str())
+    }
+}
+
+impl Profile {
+    /// Adds a PC sample. `None` stands for the core sleeping.
+    pub fn add(&mut self, symbols: &Symbols, pc: Option<u32>) {
+        let name = match pc {
+            Some(pc) => symbols.lookup(pc).map_or_else(|| format!("0x{:08x}", pc), Into::into),
+            None => SLEEP.into(),
+        };
+        *self.samples.entry(name).or_default() += 1;
+        self.total += 1;
+    }
+
+    /// Total number of samples.
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// Returns functions with their sample counts, most sampled first.
+    pub fn flat(&self) -> Vec<(&str, u32)> {
+        let mut flat =
+            self.samples.iter().map(|(name, count)| (name.as_str(), *count)).collect::<Vec<_>>();
+        flat.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        flat
+    }
+
+    /// Writes the profile in the folded stacks format for flamegraph tools.
+    ///
+    /// PC sampling doesn't capture call stacks, so every stack consists of the
+    /// sampled function only. `;` separates frames in this format, so it is
+    /// replaced in function names.
+    pub fn write_folded(&self, w: &mut impl Write) -> Result<()> {
+        for (name, count) in self.flat() {
+            writeln!(w, "{} {}", name.replace(';', ":"), count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the flat profile, one `function count` line per function.
+    pub fn write_flat(&self, w: &mut impl Write) -> Result<()> {
+        for (name, count) in self.flat() {
+            writeln!(w, "{} {}", name, count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+//! Serial endpoint configuration and discovery.
+
+use anyhow::{bail, Result};
+use drone_config::{self as config, SerialFlowControl, SerialParity};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Prefix of an endpoint selected by USB attributes.
+pub const USB_PREFIX: &str = "usb:";
+
+const SYS_CLASS_TTY: &str = "/sys/class/tty";
+
+/// Linux `termios2` interface, which accepts arbitrary baud rates. The values
+/// are for the asm-generic and x86 ioctl layouts, other architectures (e.g.
+/// powerpc, mips, sparc) encode them differently.
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64"
+))]
+mod termios2 {
+    pub const TCGETS2: libc::c_ulong = 0x802C_542A;
+    pub const TCSETS2: libc::c_ulong = 0x402C_542B;
+    pub const BOTHER: libc::tcflag_t = 0o010_000;
+    const KERNEL_NCCS: usize = 19;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Termios2 {
+        pub c_iflag: libc::tcflag_t,
+        pub c_oflag: libc::tcflag_t,
+        pub c_cflag: libc::tcflag_t,
+        pub c_lflag: libc::tcflag_t,
+        pub c_line: libc::cc_t,
+        pub c_cc: [libc::cc_t; KERNEL_NCCS],
+        pub c_ispeed: libc::speed_t,
+        pub c_ospeed: libc::speed_t,
+    }
+}
+
+/// USB serial adapter found in sysfs.
+#[derive(Debug)]
+pub struct UsbSerial {
+    /// Device path.
+    pub path: PathBuf,
+    /// Vendor ID.
+    pub vid: u16,
+    /// Product ID.
+    pub pid: u16,
+    /// Serial number.
+    pub serial: Option<String>,
+    /// Interface number.
+    pub interface: Option<u8>,
+}
+
+struct UsbSelector {
+    vid: u16,
+    pid: u16,
+    serial: Option<String>,
+    interface: Option<u8>,
+}
+
+/// Configures the serial `endpoint` in raw mode with the given baud rate,
+/// parity, and flow control.
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64"
+))]
+pub fn configure(
+    endpoint: &str,
+    baud_rate: u32,
+    parity: SerialParity,
+    flow_control: SerialFlowControl,
+) -> Result<()> {
+    use std::{
+        fs::OpenOptions,
+        io,
+        os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    };
+    use termios2::{Termios2, BOTHER, TCGETS2, TCSETS2};
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+        .open(endpoint)?;
+    let fd = file.as_raw_fd();
+    let mut tio = Termios2::default();
+    if unsafe { libc::ioctl(fd, TCGETS2, &mut tio) } == -1 {
+        bail!("Couldn't read `{}` settings: {}", endpoint, io::Error::last_os_error());
+    }
+    tio.c_iflag &= !(libc::IGNBRK
+        | libc::BRKINT
+        | libc::PARMRK
+        | libc::ISTRIP
+        | libc::INLCR
+        | libc::IGNCR
+        | libc::ICRNL
+        | libc::IXON
+        | libc::IXOFF
+        | libc::IXANY
+        | libc::INPCK);
+    tio.c_oflag &= !libc::OPOST;
+    tio.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
+    tio.c_cflag &=
+        !(libc::CSIZE | libc::PARENB | libc::PARODD | libc::CSTOPB | libc::CRTSCTS | libc::CBAUD);
+    tio.c_cflag |= libc::CS8 | libc::CREAD | libc::CLOCAL | BOTHER;
+    match parity {
+        SerialParity::None => {}
+        SerialParity::Odd => {
+            tio.c_cflag |= libc::PARENB | libc::PARODD;
+            tio.c_iflag |= libc::INPCK;
+        }
+        SerialParity::Even => {
+            tio.c_cflag |= libc::PARENB;
+            tio.c_iflag |= libc::INPCK;
+        }
+    }
+    match flow_control {
+        SerialFlowControl::None => {}
+        SerialFlowControl::Hardware => tio.c_cflag |= libc::CRTSCTS,
+        SerialFlowControl::Software => tio.c_iflag |= libc::IXON | libc::IXOFF,
+    }
+    tio.c_ispeed = baud_rate;
+    tio.c_ospeed = baud_rate;
+    tio.c_cc[libc::VMIN] = 1;
+    tio.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::ioctl(fd, TCSETS2, &tio) } == -1 {
+        bail!("Couldn't configure `{}`: {}", endpoint, io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Configures the serial `endpoint` in raw mode with the given baud rate,
+/// parity, and flow control.
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64"
+)))]
+pub fn configure(
+    endpoint: &str,
+    _baud_rate: u32,
+    _parity: SerialParity,
+    _flow_control: SerialFlowControl,
+) -> Result<()> {
+    bail!("Configuring serial endpoint `{}` is not supported on this architecture", endpoint);
+}
+
+/// Resolves the `endpoint` selected by USB attributes to the device path.
+///
+/// The format is `usb:VID:PID[:SERIAL[:INTERFACE]]`, where VID and PID are
+/// hexadecimal, and SERIAL can be `*` to match any serial number. Other
+/// endpoints are returned as is.
+pub fn resolve_endpoint(endpoint: &str) -> Result<String> {
+    if !endpoint.starts_with(USB_PREFIX) {
+        return Ok(endpoint.to_string());
+    }
+    let selector = UsbSelector::parse(&endpoint[USB_PREFIX.len()..])?;
+    let adapters = usb_serials()?;
+    let mut matches = adapters.iter().filter(|adapter| selector.matches(adapter));
+    match (matches.next(), matches.next()) {
+        (Some(adapter), None) => {
+            log::info!("Resolved `{}` to `{}`", endpoint, adapter.path.display());
+            Ok(adapter.path.to_string_lossy().into_owned())
+        }
+        (Some(_), Some(_)) => bail!(
+            "Multiple serial adapters match `{}`, specify the serial number or interface",
+            endpoint
+        ),
+        (None, _) => {
+            let available = adapters.iter().map(UsbSerial::to_endpoint).collect::<Vec<_>>();
+            bail!(
+                "No serial adapter matches `{}` (available: {})",
+                endpoint,
+                if available.is_empty() { "none".to_string() } else { available.join(", ") }
+            )
+        }
+    }
+}
+
+/// Resolves all serial endpoints of the `config` selected by USB attributes.
+pub fn resolve_endpoints(config: &mut config::Config) -> Result<()> {
+    if let Some(bmp) = config.probe.as_mut().and_then(|probe| probe.bmp.as_mut()) {
+        bmp.gdb_endpoint = resolve_endpoint(&bmp.gdb_endpoint)?;
+    }
+    if let Some(log) = &mut config.log {
+        if let Some(endpoint) = log.swo.as_mut().and_then(|swo| swo.serial_endpoint.as_mut()) {
+            *endpoint = resolve_endpoint(endpoint)?;
+        }
+        if let Some(dso) = &mut log.dso {
+            dso.serial_endpoint = resolve_endpoint(&dso.serial_endpoint)?;
+        }
+        if let Some(endpoint) = log.rtt.as_mut().and_then(|rtt| rtt.serial_endpoint.as_mut()) {
+            *endpoint = resolve_endpoint(endpoint)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns all USB serial adapters found in sysfs.
+pub fn usb_serials() -> Result<Vec<UsbSerial>> {
+    let mut adapters = Vec::new();
+    for entry in fs::read_dir(SYS_CLASS_TTY)? {
+        let entry = entry?;
+        let device = match fs::canonicalize(entry.path().join("device")) {
+            Ok(device) => device,
+            Err(_) => continue,
+        };
+        let interface = device
+            .ancestors()
+            .find_map(|dir| read_attr(dir, "bInterfaceNumber"))
+            .and_then(|number| u8::from_str_radix(&number, 16).ok());
+        let usb = match device.ancestors().find(|dir| dir.join("idVendor").exists()) {
+            Some(usb) => usb,
+            None => continue,
+        };
+        let id = |name| read_attr(usb, name).and_then(|id| u16::from_str_radix(&id, 16).ok());
+        if let (Some(vid), Some(pid)) = (id("idVendor"), id("idProduct")) {
+            adapters.push(UsbSerial {
+                path: Path::new("/dev").join(entry.file_name()),
+                vid,
+                pid,
+                serial: read_attr(usb, "serial"),
+                interface,
+            });
+        }
+    }
+    adapters.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(adapters)
+}
+
+impl UsbSerial {
+    /// Returns the most specific endpoint selecting the adapter.
+    pub fn to_endpoint(&self) -> String {
+        let mut endpoint = format!("{}{:04x}:{:04x}", USB_PREFIX, self.vid, self.pid);
+        if self.serial.is_some() || self.interface.is_some() {
+            endpoint.push(':');
+            endpoint.push_str(self.serial.as_deref().unwrap_or("*"));
+        }
+        if let Some(interface) = self.interface {
+            endpoint.push_str(&format!(":{}", interface));
+        }
+        endpoint
+    }
+}
+
+impl UsbSelector {
+    fn parse(src: &str) -> Result<Self> {
+        let fields = src.split(':').collect::<Vec<_>>();
+        let (vid, pid, serial, interface) = match fields.as_slice() {
+            [vid, pid] => (vid, pid, None, None),
+            [vid, pid, serial] => (vid, pid, Some(serial), None),
+            [vid, pid, serial, interface] => (vid, pid, Some(serial), Some(interface)),
+            _ => bail!(
+                "Invalid USB endpoint `{}{}`, expected VID:PID[:SERIAL[:INTERFACE]]",
+                USB_PREFIX,
+                src
+            ),
+        };
+        Ok(Self {
+            vid: u16::from_str_radix(vid, 16)?,
+            pid: u16::from_str_radix(pid, 16)?,
+            serial: serial.filter(|&&serial| serial != "*").map(|serial| (*serial).to_string()),
+            interface: interface.map(|interface| interface.parse()).transpose()?,
+        })
+    }
+
+    fn matches(&self, adapter: &UsbSerial) -> bool {
+        self.vid == adapter.vid
+            && self.pid == adapter.pid
+            && self.serial.as_ref().map_or(true, |serial| adapter.serial.as_ref() == Some(serial))
+            && self.interface.map_or(true, |interface| adapter.interface == Some(interface))
+    }
+}
+
+fn read_attr(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name)).ok().map(|value| value.trim().to_string())
+}
@@ -65,6 +65,21 @@ impl HelperDef for Get {
             None => Ok(None),
         }
     }
+
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'_>,
+        ctx: &'rc Context,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        // Variables hold already rendered text, so they are written unescaped.
+        if let Some(value) = self.call_inner(h, r, ctx, rc)? {
+            out.write(&value.render())?;
+        }
+        Ok(())
+    }
 }
 
 pub struct Replace;
@@ -4,6 +4,8 @@ pub mod helpers;
 
 use crate::{
     devices::Device,
+    log,
+    log::rtt::ControlBlock,
     probe::{Log, Probe},
     utils::{ser_to_string, temp_dir},
 };
@@ -28,6 +30,9 @@ impl Registry<'_> {
         }
 
         template!("layout.ld")?;
+        template!("cortexm.gdb")?;
+        template!("dso.gdb")?;
+        template!("semihosting.gdb")?;
         template!("new/src-cortexm/bin.rs")?;
         template!("new/src-cortexm/lib.rs")?;
         template!("new/src-cortexm/thr.rs")?;
@@ -42,6 +47,8 @@ impl Registry<'_> {
         template!("bmp/flash.gdb")?;
         template!("bmp/gdb.gdb")?;
         template!("bmp/reset.gdb")?;
+        template!("bmp/rtt.gdb")?;
+        template!("bmp/semihosting.gdb")?;
         template!("bmp/swo.gdb")?;
         template!("bmp/target.gdb")?;
         template!("bmp/target/cortexm.gdb")?;
@@ -50,10 +57,14 @@ impl Registry<'_> {
         template!("jlink/flash.jlink")?;
         template!("jlink/gdb.gdb")?;
         template!("jlink/dso.gdb")?;
+        template!("jlink/rtt.gdb")?;
+        template!("jlink/semihosting.gdb")?;
         template!("openocd/flash.openocd")?;
         template!("openocd/gdb.gdb")?;
         template!("openocd/gdb.openocd")?;
         template!("openocd/reset.openocd")?;
+        template!("openocd/rtt.gdb")?;
+        template!("openocd/semihosting.gdb")?;
         template!("openocd/swo.gdb")?;
 
         helpers::register(&mut handlebars);
@@ -250,18 +261,41 @@ impl Registry<'_> {
         config: &Config,
         ports: &BTreeSet<u32>,
         reset: bool,
+        options: &log::Options,
         pipe: &Path,
     ) -> Result<NamedTempFile> {
         let data = json!({
             "config": config,
             "ports": ports,
             "reset": reset,
+            "timestamps": options.timestamps,
+            "pc_sampling": options.pc_sampling,
+            "exception_trace": options.exception_trace,
             "pipe": pipe,
         });
         helpers::clear_vars();
         named_temp_file(|file| self.0.render_to_write("bmp/swo.gdb", &data, file))
     }
 
+    /// Renders BMP `rtt` command script.
+    pub fn bmp_rtt(
+        &self,
+        config: &Config,
+        reset: bool,
+        control_block: ControlBlock,
+        pipe: &Path,
+    ) -> Result<NamedTempFile> {
+        let data = json!({
+            "config": config,
+            "reset": reset,
+            "control_block": control_block,
+            "control_block_end": control_block.end(),
+            "pipe": pipe,
+        });
+        helpers::clear_vars();
+        named_temp_file(|file| self.0.render_to_write("bmp/rtt.gdb", &data, file))
+    }
+
     /// Renders J-Link `reset` command script.
     pub fn jlink_reset(&self) -> Result<NamedTempFile> {
         helpers::clear_vars();
@@ -309,6 +343,24 @@ impl Registry<'_> {
         named_temp_file(|file| self.0.render_to_write("jlink/dso.gdb", &data, file))
     }
 
+    /// Renders J-Link `rtt` command script.
+    pub fn jlink_rtt(
+        &self,
+        config: &Config,
+        reset: bool,
+        control_block: ControlBlock,
+        pipe: &Path,
+    ) -> Result<NamedTempFile> {
+        let data = json!({
+            "config": config,
+            "reset": reset,
+            "control_block": control_block,
+            "pipe": pipe,
+        });
+        helpers::clear_vars();
+        named_temp_file(|file| self.0.render_to_write("jlink/rtt.gdb", &data, file))
+    }
+
     /// Renders OpenOCD `reset` command script.
     pub fn openocd_reset(&self) -> Result<String> {
         helpers::clear_vars();
@@ -345,12 +397,37 @@ impl Registry<'_> {
         Ok(self.0.render("openocd/gdb.openocd", &data)?)
     }
 
+    /// Renders OpenOCD `rtt` command script.
+    pub fn openocd_rtt(
+        &self,
+        config: &Config,
+        reset: bool,
+        control_block: ControlBlock,
+        servers: &[(u32, u16)],
+        pipe: &Path,
+    ) -> Result<NamedTempFile> {
+        let servers = servers
+            .iter()
+            .map(|(channel, port)| json!({ "channel": channel, "port": port }))
+            .collect::<Vec<_>>();
+        let data = json!({
+            "config": config,
+            "reset": reset,
+            "control_block": control_block,
+            "servers": servers,
+            "pipe": pipe,
+        });
+        helpers::clear_vars();
+        named_temp_file(|file| self.0.render_to_write("openocd/rtt.gdb", &data, file))
+    }
+
     /// Renders OpenOCD `swo` command script.
     pub fn openocd_swo(
         &self,
         config: &Config,
         ports: &BTreeSet<u32>,
         reset: bool,
+        options: &log::Options,
         pipe: &Path,
         output: Option<&Path>,
     ) -> Result<NamedTempFile> {
@@ -358,6 +435,9 @@ impl Registry<'_> {
             "config": config,
             "ports": ports,
             "reset": reset,
+            "timestamps": options.timestamps,
+            "pc_sampling": options.pc_sampling,
+            "exception_trace": options.exception_trace,
             "pipe": pipe,
             "output": output,
         });
@@ -0,0 +1,146 @@
+//! On-target test result protocol.
+//!
+//! The test harness running on the target reports the results on a dedicated
+//! log port, one event per line:
+//!
+//! ```text
+//! START <name>
+//! PASS <name>
+//! FAIL <name> [message]
+//! IGNORE <name>
+//! SUMMARY <passed> <failed> <ignored>
+//! ```
+//!
+//! The `SUMMARY` line ends the test run. Lines not matching the protocol are
+//! ignored.
+
+/// Test result protocol event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A test has started.
+    Started(String),
+    /// A test has passed.
+    Passed(String),
+    /// A test has failed with an optional message.
+    Failed(String, String),
+    /// A test has been ignored.
+    Ignored(String),
+    /// The test run has finished with the counts reported by the target.
+    Summary {
+        /// Number of passed tests.
+        passed: usize,
+        /// Number of failed tests.
+        failed: usize,
+        /// Number of ignored tests.
+        ignored: usize,
+    },
+}
+
+/// Collected test results.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Names of passed tests.
+    pub passed: Vec<String>,
+    /// Names and messages of failed tests.
+    pub failed: Vec<(String, String)>,
+    /// Names of ignored tests.
+    pub ignored: Vec<String>,
+    /// Name of the test started but not yet finished.
+    pub running: Option<String>,
+    /// Counts reported by the target at the end of the run.
+    pub summary: Option<(usize, usize, usize)>,
+}
+
+impl Event {
+    /// Parses a protocol line. Returns `None` if the line is not a protocol
+    /// event.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim_end().splitn(2, ' ');
+        let kind = fields.next()?;
+        let rest = fields.next().unwrap_or("");
+        let test = || (!rest.is_empty()).then(|| rest.to_string());
+        match kind {
+            "START" => test().map(Self::Started),
+            "PASS" => test().map(Self::Passed),
+            "IGNORE" => test().map(Self::Ignored),
+            "FAIL" => {
+                let mut fields = rest.splitn(2, ' ');
+                let name = fields.next().filter(|name| !name.is_empty())?;
+                Some(Self::Failed(name.to_string(), fields.next().unwrap_or("").to_string()))
+            }
+            "SUMMARY" => {
+                let counts = rest
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<Vec<usize>, _>>()
+                    .ok()?;
+                match counts.as_slice() {
+                    [passed, failed, ignored] => {
+                        Some(Self::Summary { passed: *passed, failed: *failed, ignored: *ignored })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Report {
+    /// Records the `event`.
+    pub fn push(&mut self, event: Event) {
+        match event {
+            Event::Started(name) => {
+                self.abort("started another test before finishing");
+                self.running = Some(name);
+            }
+            Event::Passed(name) => {
+                self.finish(&name);
+                self.passed.push(name);
+            }
+            Event::Failed(name, message) => {
+                self.finish(&name);
+                self.failed.push((name, message));
+            }
+            Event::Ignored(name) => {
+                self.finish(&name);
+                self.ignored.push(name);
+            }
+            Event::Summary { passed, failed, ignored } => {
+                self.abort("didn't finish before the summary");
+                self.summary = Some((passed, failed, ignored));
+            }
+        }
+    }
+
+    /// Marks the running test, if any, as failed with `reason`.
+    pub fn abort(&mut self, reason: &str) {
+        if let Some(name) = self.running.take() {
+            self.failed.push((name, reason.to_string()));
+        }
+    }
+
+    /// Returns `true` if the test run has finished without failures.
+    ///
+    /// A failure count reported in the summary, or a summary disagreeing with
+    /// the collected results, fails the run too.
+    pub fn is_success(&self) -> bool {
+        matches!(self.summary, Some((_, 0, _))) && self.failed.is_empty() && self.is_consistent()
+    }
+
+    /// Returns `true` if the counts reported by the target agree with the
+    /// collected results.
+    pub fn is_consistent(&self) -> bool {
+        self.summary.map_or(true, |summary| {
+            summary == (self.passed.len(), self.failed.len(), self.ignored.len())
+        })
+    }
+
+    fn finish(&mut self, name: &str) {
+        if self.running.as_deref() == Some(name) {
+            self.running = None;
+        } else {
+            self.abort("didn't finish before the next test");
+        }
+    }
+}
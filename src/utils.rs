@@ -153,6 +153,9 @@ pub fn check_root_result(color: Color, f: impl FnOnce() -> Result<()>) {
         Err(err) if err.is::<SignalError>() => {
             exit(1);
         }
+        Err(err) if err.is::<ExitCodeError>() => {
+            exit(err.downcast_ref::<ExitCodeError>().unwrap().0);
+        }
         Err(err) => {
             eprintln!("{}: {:?}", color.bold_fg("Error", Red), err);
             exit(1);
@@ -165,6 +168,27 @@ pub fn ser_to_string<T: ser::Serialize>(value: T) -> String {
     serde_json::to_value(value).unwrap().as_str().unwrap().to_string()
 }
 
+/// Parses a duration with an optional unit suffix: `ms`, `s`, `m`, or `h`.
+/// Seconds are assumed if the suffix is omitted.
+pub fn parse_duration(src: &str) -> Result<Duration> {
+    let (number, scale) = if src.ends_with("ms") {
+        (&src[..src.len() - 2], 0.001)
+    } else if src.ends_with('s') {
+        (&src[..src.len() - 1], 1.0)
+    } else if src.ends_with('m') {
+        (&src[..src.len() - 1], 60.0)
+    } else if src.ends_with('h') {
+        (&src[..src.len() - 1], 3600.0)
+    } else {
+        (src, 1.0)
+    };
+    let seconds = number.parse::<f64>()? * scale;
+    if !seconds.is_finite() || seconds < 0.0 {
+        bail!("Invalid duration `{}`", src);
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
 /// Deserialize a value from the string.
 pub fn de_from_str<T: de::DeserializeOwned>(s: &str) -> Result<T> {
     serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(Into::into)
@@ -173,3 +197,33 @@ pub fn de_from_str<T: de::DeserializeOwned>(s: &str) -> Result<T> {
 #[derive(Error, Debug)]
 #[error("signal")]
 struct SignalError;
+
+/// Error carrying the exit status of the process.
+#[derive(Error, Debug)]
+#[error("exit code {0}")]
+pub struct ExitCodeError(pub i32);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_units() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+    }
+
+    #[test]
+    fn duration_without_unit() {
+        assert_eq!(parse_duration("1.5").unwrap(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn invalid_duration() {
+        for src in &["", "ms", "10x", "-1s", "infs", "NaN"] {
+            assert!(parse_duration(src).is_err(), "`{}` accepted", src);
+        }
+    }
+}